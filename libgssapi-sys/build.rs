@@ -18,6 +18,26 @@ enum Gssapi {
     Apple,
 }
 
+impl Gssapi {
+    fn name(&self) -> &'static str {
+        match self {
+            Gssapi::Mit => "mit",
+            Gssapi::Heimdal => "heimdal",
+            Gssapi::Apple => "apple",
+        }
+    }
+}
+
+// Tell this crate and its dependents which implementation was
+// found. Dependents get it as DEP_GSSAPI_KRB5_IMPL in their build
+// script (see libgssapi/build.rs), since rustc-cfg only applies to
+// this crate.
+fn emit_cfg(imp: &Gssapi) {
+    println!("cargo:rustc-check-cfg=cfg(gssapi_mit, gssapi_heimdal, gssapi_apple)");
+    println!("cargo:rustc-cfg=gssapi_{}", imp.name());
+    println!("cargo:impl={}", imp.name());
+}
+
 fn builder_from_pkgconfig(lib: pkg_config::Library) -> bindgen::Builder {
     bindgen::Builder::default().clang_args(
         lib.include_paths
@@ -36,6 +56,40 @@ fn try_pkgconfig() -> Result<(Gssapi, bindgen::Builder), pkg_config::Error> {
     }
 }
 
+// Let the user skip detection entirely, for cross builds and
+// sandboxes where find and krb5-config aren't available. Setting
+// GSSAPI_IMPL (mit, heimdal, or apple) is enough, GSSAPI_LIB_NAME
+// overrides the library to link, and GSSAPI_LIB_DIR adds a search
+// path.
+fn from_env() -> Option<Gssapi> {
+    for var in ["GSSAPI_IMPL", "GSSAPI_LIB_NAME", "GSSAPI_LIB_DIR"] {
+        println!("cargo:rerun-if-env-changed={}", var);
+    }
+    let imp = env::var("GSSAPI_IMPL").ok();
+    let name = env::var("GSSAPI_LIB_NAME").ok();
+    let dir = env::var("GSSAPI_LIB_DIR").ok();
+    let imp = match (imp.as_deref(), name.as_deref()) {
+        (None, None) if dir.is_none() => return None,
+        (Some("mit"), _) | (None, Some("gssapi_krb5")) => Gssapi::Mit,
+        (Some("heimdal"), _) | (None, Some("gssapi")) => Gssapi::Heimdal,
+        (Some("apple"), _) => Gssapi::Apple,
+        (Some(imp), _) => {
+            panic!("unknown GSSAPI_IMPL {}, expected mit, heimdal, or apple", imp)
+        }
+        (None, _) => panic!("GSSAPI_IMPL must be set to mit, heimdal, or apple"),
+    };
+    if let Some(dir) = dir {
+        println!("cargo:rustc-link-search=native={}", dir);
+    }
+    match (name, &imp) {
+        (Some(name), _) => println!("cargo:rustc-link-lib={}", name),
+        (None, Gssapi::Mit) => println!("cargo:rustc-link-lib=gssapi_krb5"),
+        (None, Gssapi::Heimdal) => println!("cargo:rustc-link-lib=gssapi"),
+        (None, Gssapi::Apple) => println!("cargo:rustc-link-lib=framework=GSS"),
+    }
+    Some(imp)
+}
+
 fn which() -> Gssapi {
     let target_os = env::var("CARGO_CFG_TARGET_OS").unwrap();
     let target_family = env::var("CARGO_CFG_TARGET_FAMILY").unwrap();
@@ -77,10 +131,16 @@ fn which() -> Gssapi {
 fn main() {
     let cross_compile = env::var("HOST").unwrap() != env::var("TARGET").unwrap();
 
-    let (imp, builder) = match (cross_compile, try_pkgconfig()) {
-        (false, Ok((imp, builder))) => (imp, builder),
-        _ => {
-            let imp = which();
+    let env_imp = from_env();
+    let pkgconfig = match env_imp {
+        Some(_) => None,
+        None if cross_compile => None,
+        None => try_pkgconfig().ok(),
+    };
+    let (imp, builder) = match pkgconfig {
+        Some((imp, builder)) => (imp, builder),
+        None => {
+            let imp = env_imp.unwrap_or_else(which);
             let builder = bindgen::Builder::default();
             let nix_cflags = env::var("NIX_CFLAGS_COMPILE");
             let builder = match imp {
@@ -94,6 +154,7 @@ fn main() {
             (imp, builder)
         }
     };
+    emit_cfg(&imp);
     let bindings = builder
         .allowlist_type("(OM_.+|gss_.+)")
         .allowlist_var("_?GSS_.+|gss_.+")
@@ -1,3 +1,4 @@
 #include <gssapi.h>
 #include <gssapi/gssapi_ext.h>
+#include <gssapi/gssapi_krb5.h>
 #include "consts.h"
@@ -0,0 +1,12 @@
+use std::env;
+
+// Turn the implementation libgssapi-sys detected into a cfg, so
+// wrappers for functions that only some implementations have can be
+// compiled out where they don't exist.
+fn main() {
+    println!("cargo:rustc-check-cfg=cfg(gssapi_mit, gssapi_heimdal, gssapi_apple)");
+    println!("cargo:rerun-if-env-changed=DEP_GSSAPI_KRB5_IMPL");
+    if let Ok(imp) = env::var("DEP_GSSAPI_KRB5_IMPL") {
+        println!("cargo:rustc-cfg=gssapi_{}", imp);
+    }
+}
@@ -1,6 +1,7 @@
 use libgssapi::credential::{Cred, CredUsage};
 use libgssapi::name::Name;
 use libgssapi::oid::{OidSet, GSS_MECH_KRB5, GSS_NT_KRB5_PRINCIPAL};
+use libgssapi::util::Lifetime;
 
 fn main() {
     let desired_mechs = {
@@ -11,7 +12,7 @@ fn main() {
 
     let name = Name::new("user@EXAMPLE.ORG".as_ref(), Some(&GSS_NT_KRB5_PRINCIPAL)).expect("can't create name");
     let cred = Cred::pass_acquire(
-        Some(&name), "SuperSecret", None, CredUsage::Initiate, Some(&desired_mechs)
+        Some(&name), "SuperSecret", Lifetime::Indefinite, CredUsage::Initiate, Some(&desired_mechs)
     ).expect("can't create credential");
 
     println!("cred: {:?}", cred);
@@ -5,12 +5,12 @@
 
 use bytes::BytesMut;
 use libgssapi::{
-    context::{ClientCtx, CtxFlags, SecurityContext, ServerCtx},
+    context::{ClientCtx, CtxFlags, SecurityContext, ServerCtx, Step},
     credential::{Cred, CredUsage},
     error::Error,
     name::Name,
     oid::{OidSet, GSS_MECH_KRB5, GSS_NT_HOSTBASED_SERVICE},
-    util::{Buf, GssIov, GssIovFake, GssIovType},
+    util::{Buf, GssIov, GssIovFake, GssIovType, Lifetime},
 };
 use std::env::args;
 
@@ -23,24 +23,32 @@ fn setup_server_ctx(
     let cname = name.canonicalize(Some(&GSS_MECH_KRB5))?;
     println!("canonicalize name for kerberos 5");
     println!("server name: {}, server cname: {}", name, cname);
-    let server_cred =
-        Cred::acquire(Some(&cname), None, CredUsage::Accept, Some(desired_mechs))?;
+    let server_cred = Cred::acquire(
+        Some(&cname),
+        Lifetime::Indefinite,
+        CredUsage::Accept,
+        Some(desired_mechs),
+    )?;
     println!("acquired server credentials: {:#?}", server_cred.info()?);
-    Ok((ServerCtx::new(server_cred), cname))
+    Ok((ServerCtx::new(Some(&server_cred)), cname))
 }
 
 fn setup_client_ctx(
     service_name: Name,
     desired_mechs: &OidSet,
 ) -> Result<ClientCtx, Error> {
-    let client_cred =
-        Cred::acquire(None, None, CredUsage::Initiate, Some(&desired_mechs))?;
+    let client_cred = Cred::acquire(
+        None,
+        Lifetime::Indefinite,
+        CredUsage::Initiate,
+        Some(&desired_mechs),
+    )?;
     println!(
         "acquired default client credentials: {:#?}",
         client_cred.info()?
     );
     Ok(ClientCtx::new(
-        Some(client_cred),
+        Some(&client_cred),
         service_name,
         CtxFlags::GSS_C_MUTUAL_FLAG,
         Some(&GSS_MECH_KRB5),
@@ -183,12 +191,12 @@ fn run(service_name: &[u8]) -> Result<(), Error> {
     let mut client_ctx = setup_client_ctx(cname, &desired_mechs)?;
     let mut server_tok: Option<Buf> = None;
     loop {
-        match client_ctx.step(server_tok.as_ref().map(|b| &**b), None)? {
+        match client_ctx.step(server_tok.as_deref(), None)?.into_token() {
             None => break,
             Some(client_tok) => match server_ctx.step(&*client_tok)? {
-                None => break,
-                Some(tok) => {
-                    server_tok = Some(tok);
+                Step::Done(None) => break,
+                Step::Continue(tok) | Step::Done(tok) => {
+                    server_tok = tok;
                 }
             },
         }
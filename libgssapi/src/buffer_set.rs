@@ -0,0 +1,161 @@
+use crate::{
+    error::{Error, MajorFlags},
+    util::{Buf, BufRef},
+};
+use libgssapi_sys::{
+    gss_add_buffer_set_member, gss_create_empty_buffer_set, gss_release_buffer_set,
+    gss_buffer_set_t, OM_uint32, GSS_S_COMPLETE,
+};
+use std::{
+    mem,
+    ops::{Deref, DerefMut},
+    ptr, slice, vec,
+};
+
+/// This represents an owned set of buffers we got from gssapi (or
+/// built with `add`), it will be deallocated via the library routine
+/// when it is dropped. It derefs to a slice of `Buf`.
+#[repr(transparent)]
+#[derive(Debug)]
+pub struct BufferSet(gss_buffer_set_t);
+
+unsafe impl Send for BufferSet {}
+unsafe impl Sync for BufferSet {}
+
+impl Drop for BufferSet {
+    fn drop(&mut self) {
+        if !self.0.is_null() {
+            let mut minor = GSS_S_COMPLETE;
+            let _major = unsafe {
+                gss_release_buffer_set(
+                    &mut minor as *mut OM_uint32,
+                    &mut self.0 as *mut gss_buffer_set_t,
+                )
+            };
+        }
+    }
+}
+
+impl Deref for BufferSet {
+    type Target = [Buf];
+
+    fn deref(&self) -> &Self::Target {
+        unsafe {
+            if self.0.is_null() || (*self.0).elements.is_null() {
+                &[]
+            } else {
+                // Buf is repr(transparent) over gss_buffer_desc
+                slice::from_raw_parts((*self.0).elements.cast(), (*self.0).count)
+            }
+        }
+    }
+}
+
+impl DerefMut for BufferSet {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        unsafe {
+            if self.0.is_null() || (*self.0).elements.is_null() {
+                &mut []
+            } else {
+                slice::from_raw_parts_mut((*self.0).elements.cast(), (*self.0).count)
+            }
+        }
+    }
+}
+
+/// Take ownership of the buffers in the set. The set itself is
+/// released immediately.
+impl IntoIterator for BufferSet {
+    type Item = Buf;
+    type IntoIter = vec::IntoIter<Buf>;
+
+    fn into_iter(mut self) -> Self::IntoIter {
+        self.iter_mut()
+            .map(|b| mem::replace(b, Buf::empty()))
+            .collect::<Vec<_>>()
+            .into_iter()
+    }
+}
+
+impl<'a> IntoIterator for &'a BufferSet {
+    type Item = &'a Buf;
+    type IntoIter = slice::Iter<'a, Buf>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.iter()
+    }
+}
+
+impl BufferSet {
+    /// Create a new empty buffer set
+    pub fn new() -> Result<Self, Error> {
+        let mut minor = GSS_S_COMPLETE;
+        let mut set = ptr::null_mut();
+        let major = unsafe {
+            gss_create_empty_buffer_set(
+                &mut minor as *mut OM_uint32,
+                &mut set as *mut gss_buffer_set_t,
+            )
+        };
+        if major == GSS_S_COMPLETE {
+            Ok(BufferSet(set))
+        } else {
+            Err(Error {
+                major: MajorFlags::from_bits_retain(major),
+                minor,
+            })
+        }
+    }
+
+    /// An unallocated set, to be filled in by a gssapi function
+    #[allow(dead_code)]
+    pub(crate) fn empty() -> Self {
+        BufferSet(ptr::null_mut())
+    }
+
+    #[allow(dead_code)]
+    pub(crate) unsafe fn to_c(&mut self) -> &mut gss_buffer_set_t {
+        &mut self.0
+    }
+
+    /// Add a copy of `buf` to the end of the set
+    pub fn add(&mut self, buf: &[u8]) -> Result<(), Error> {
+        let mut minor = GSS_S_COMPLETE;
+        let mut buf = BufRef::from(buf);
+        let major = unsafe {
+            gss_add_buffer_set_member(
+                &mut minor as *mut OM_uint32,
+                buf.to_c(),
+                &mut self.0 as *mut gss_buffer_set_t,
+            )
+        };
+        if major == GSS_S_COMPLETE {
+            Ok(())
+        } else {
+            Err(Error {
+                major: MajorFlags::from_bits_retain(major),
+                minor,
+            })
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_buffer_set() {
+        let mut set = BufferSet::new().expect("Failed to create buffer set");
+        assert!(set.is_empty());
+        set.add(b"foo").expect("Failed to add buffer");
+        set.add(b"").expect("Failed to add buffer");
+        set.add(b"bar").expect("Failed to add buffer");
+        assert_eq!(set.len(), 3);
+        let bufs = (&set).into_iter().map(|b| b.to_vec()).collect::<Vec<_>>();
+        assert_eq!(bufs, vec![b"foo".to_vec(), vec![], b"bar".to_vec()]);
+        let bufs = set.into_iter().map(Buf::into_vec).collect::<Vec<_>>();
+        assert_eq!(bufs, vec![b"foo".to_vec(), vec![], b"bar".to_vec()]);
+        assert!(BufferSet::empty().is_empty());
+    }
+}
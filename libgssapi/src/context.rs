@@ -2,28 +2,53 @@
 use crate::util::{GssIov, GssIovFake};
 use crate::{
     credential::{Cred, NO_CRED},
-    error::{gss_error, Error, MajorFlags},
+    error::{gss_error, Error, MajorFlags, TokenStatus},
     name::Name,
     oid::{Oid, NO_OID},
-    util::{Buf, BufRef},
+    util::{Buf, BufRef, Lifetime},
 };
 use libgssapi_sys::{
-    gss_OID, gss_accept_sec_context, gss_buffer_desc, gss_channel_bindings_struct,
-    gss_channel_bindings_t, gss_cred_id_struct, gss_cred_id_t, gss_ctx_id_t,
-    gss_delete_sec_context, gss_init_sec_context, gss_inquire_context, gss_name_t,
-    gss_unwrap, gss_wrap, OM_uint32, GSS_C_ANON_FLAG, GSS_C_CONF_FLAG, GSS_C_DELEG_FLAG,
-    GSS_C_DELEG_POLICY_FLAG, GSS_C_INTEG_FLAG, GSS_C_MUTUAL_FLAG, GSS_C_PROT_READY_FLAG,
-    GSS_C_QOP_DEFAULT, GSS_C_REPLAY_FLAG, GSS_C_SEQUENCE_FLAG, GSS_C_TRANS_FLAG,
-    GSS_S_COMPLETE, _GSS_C_INDEFINITE, _GSS_S_CONTINUE_NEEDED,
+    gss_OID, gss_OID_desc, gss_accept_sec_context, gss_buffer_desc, gss_channel_bindings_struct,
+    gss_channel_bindings_t, gss_context_time, gss_cred_id_struct, gss_cred_id_t,
+    gss_ctx_id_t, gss_delete_sec_context, gss_get_mic, gss_init_sec_context,
+    gss_inquire_context, gss_name_struct,
+    gss_name_t, gss_process_context_token, gss_unwrap, gss_verify_mic, gss_wrap,
+    gss_wrap_size_limit, OM_uint32, GSS_C_ANON_FLAG,
+    GSS_C_CONF_FLAG, GSS_C_DELEG_FLAG, GSS_C_DELEG_POLICY_FLAG, GSS_C_INTEG_FLAG,
+    GSS_C_MUTUAL_FLAG, GSS_C_PROT_READY_FLAG, GSS_C_QOP_DEFAULT, GSS_C_REPLAY_FLAG,
+    GSS_C_SEQUENCE_FLAG, GSS_C_TRANS_FLAG, GSS_S_COMPLETE,
+    _GSS_S_CONTINUE_NEEDED,
+};
+#[cfg(feature = "options")]
+use libgssapi_sys::gss_set_sec_context_option;
+#[cfg(all(feature = "krb5", gssapi_mit))]
+use {
+    crate::util::zeroize,
+    libgssapi_sys::{
+        gss_krb5_export_lucid_sec_context, gss_krb5_free_lucid_sec_context,
+        gss_krb5_lucid_context_v1_t, gss_krb5_lucid_key_t,
+    },
+    std::{fmt, slice},
+};
+#[cfg(feature = "inquire")]
+use {
+    crate::{buffer_set::BufferSet, util::SecretBuf},
+    libgssapi_sys::gss_inquire_sec_context_by_oid,
 };
 #[cfg(feature = "iov")]
 use libgssapi_sys::{
     gss_iov_buffer_desc, gss_unwrap_iov, gss_wrap_iov, gss_wrap_iov_length,
 };
-use std::{ffi, os::raw::c_int, ptr, time::Duration};
+use std::{ffi, os::raw::c_int, ptr};
 
 bitflags! {
-    #[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+    /// The flags of a security context. The same type is used for the
+    /// flags a client requests and the flags gssapi returns, which may
+    /// differ, e.g. if the peer doesn't support a service. Returned
+    /// flags keep any bits that aren't named here. The `Debug` output
+    /// lists the names of the set flags, e.g.
+    /// `CtxFlags(GSS_C_MUTUAL_FLAG | GSS_C_CONF_FLAG)`.
+    #[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
     pub struct CtxFlags: u32 {
         const GSS_C_DELEG_FLAG = GSS_C_DELEG_FLAG;
         const GSS_C_MUTUAL_FLAG = GSS_C_MUTUAL_FLAG;
@@ -35,6 +60,54 @@ bitflags! {
         const GSS_C_PROT_READY_FLAG = GSS_C_PROT_READY_FLAG;
         const GSS_C_TRANS_FLAG = GSS_C_TRANS_FLAG;
         const GSS_C_DELEG_POLICY_FLAG = GSS_C_DELEG_POLICY_FLAG;
+        // an MIT extension, not every implementation's headers define
+        // it, so it's spelled out here.
+        const GSS_C_CHANNEL_BOUND_FLAG = 2048;
+    }
+}
+
+/// Channel bindings tie a security context to the underlying
+/// transport (e.g. a TLS channel) so it can't be relayed over another
+/// one. Usually only `application_data` is set, the address fields are
+/// a relic of RFC 2744 and default to empty.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ChannelBindings {
+    pub initiator_addrtype: u32,
+    pub initiator_address: Vec<u8>,
+    pub acceptor_addrtype: u32,
+    pub acceptor_address: Vec<u8>,
+    pub application_data: Vec<u8>,
+}
+
+impl ChannelBindings {
+    /// Channel bindings consisting only of `application_data`, e.g.
+    /// the RFC 5929 tls-server-end-point data.
+    pub fn new(application_data: &[u8]) -> Self {
+        ChannelBindings {
+            application_data: application_data.to_vec(),
+            ..ChannelBindings::default()
+        }
+    }
+
+    /// The returned struct borrows from self, it must not outlive it
+    pub(crate) unsafe fn to_c(&self) -> gss_channel_bindings_struct {
+        fn buffer(b: &[u8]) -> gss_buffer_desc {
+            gss_buffer_desc {
+                length: b.len(),
+                value: if b.is_empty() {
+                    ptr::null_mut()
+                } else {
+                    b.as_ptr() as *mut ffi::c_void
+                },
+            }
+        }
+        gss_channel_bindings_struct {
+            initiator_addrtype: self.initiator_addrtype,
+            initiator_address: buffer(&self.initiator_address),
+            acceptor_addrtype: self.acceptor_addrtype,
+            acceptor_address: buffer(&self.acceptor_address),
+            application_data: buffer(&self.application_data),
+        }
     }
 }
 
@@ -51,21 +124,56 @@ fn delete_ctx(mut ctx: gss_ctx_id_t) {
     }
 }
 
-unsafe fn wrap(ctx: gss_ctx_id_t, encrypt: bool, msg: &[u8]) -> Result<Buf, Error> {
+/// Delete the context, leaving `ctx` null so it won't be deleted
+/// again on drop, and return the token for the other side if there
+/// is one.
+unsafe fn delete(ctx: &mut gss_ctx_id_t) -> Result<Option<Buf>, Error> {
+    if ctx.is_null() {
+        return Ok(None);
+    }
+    let mut minor = GSS_S_COMPLETE;
+    let mut out_tok = Buf::empty();
+    let major = gss_delete_sec_context(
+        &mut minor as *mut OM_uint32,
+        ctx as *mut gss_ctx_id_t,
+        out_tok.to_c(),
+    );
+    *ctx = ptr::null_mut();
+    if major == GSS_S_COMPLETE {
+        if out_tok.len() > 0 {
+            Ok(Some(out_tok))
+        } else {
+            Ok(None)
+        }
+    } else {
+        Err(Error {
+            major: MajorFlags::from_bits_retain(major),
+            minor,
+        })
+    }
+}
+
+unsafe fn wrap(
+    ctx: gss_ctx_id_t,
+    encrypt: bool,
+    qop_req: OM_uint32,
+    msg: &[u8],
+) -> Result<(Buf, bool), Error> {
     let mut minor = GSS_S_COMPLETE;
     let mut msg = BufRef::from(msg);
     let mut enc_msg = Buf::empty();
+    let mut conf_state: c_int = 0;
     let major = gss_wrap(
         &mut minor as *mut OM_uint32,
         ctx,
         if encrypt { 1 } else { 0 },
-        GSS_C_QOP_DEFAULT,
+        qop_req,
         msg.to_c(),
-        ptr::null_mut(),
+        &mut conf_state as *mut c_int,
         enc_msg.to_c(),
     );
     if major == GSS_S_COMPLETE {
-        Ok(enc_msg)
+        Ok((enc_msg, conf_state > 0))
     } else {
         Err(Error {
             major: MajorFlags::from_bits_retain(major),
@@ -126,20 +234,163 @@ unsafe fn wrap_iov_length(
     }
 }
 
-unsafe fn unwrap(ctx: gss_ctx_id_t, msg: &[u8]) -> Result<Buf, Error> {
+/// The result of unwrapping a message
+#[derive(Debug)]
+pub struct Unwrapped {
+    /// The unwrapped message
+    pub msg: Buf,
+    /// True if the message was encrypted, false if it was only
+    /// integrity protected
+    pub conf_state: bool,
+    /// The quality of protection the sender applied
+    pub qop_state: u32,
+    /// Set if the message is intact, but was replayed or arrived out
+    /// of sequence. This is only detected if `GSS_C_REPLAY_FLAG`
+    /// and/or `GSS_C_SEQUENCE_FLAG` were granted for the context
+    /// (check `flags` once it's established). The krb5 mechanism in
+    /// MIT and Heimdal honors both, SPNEGO defers to the mechanism it
+    /// negotiated, and other mechanisms may ignore them entirely.
+    pub token_status: Option<TokenStatus>,
+    // the status gss_unwrap returned, for `SecurityContext::unwrap`
+    status: Error,
+}
+
+unsafe fn unwrap(ctx: gss_ctx_id_t, msg: &[u8]) -> Result<Unwrapped, Error> {
     let mut minor = GSS_S_COMPLETE;
     let mut msg = BufRef::from(msg);
     let mut out = Buf::empty();
+    let mut conf_state: c_int = 0;
+    let mut qop_state = GSS_C_QOP_DEFAULT;
     let major = gss_unwrap(
         &mut minor as *mut OM_uint32,
         ctx,
         msg.to_c(),
         out.to_c(),
-        ptr::null_mut::<i32>(),
-        ptr::null_mut::<OM_uint32>(),
+        &mut conf_state as *mut c_int,
+        &mut qop_state as *mut OM_uint32,
+    );
+    if gss_error(major) == 0 {
+        // only supplementary bits can be set here
+        let status = Error {
+            major: MajorFlags::from_bits_retain(major),
+            minor,
+        };
+        Ok(Unwrapped {
+            msg: out,
+            conf_state: conf_state > 0,
+            qop_state,
+            token_status: status.token_status(),
+            status,
+        })
+    } else {
+        Err(Error {
+            major: MajorFlags::from_bits_retain(major),
+            minor,
+        })
+    }
+}
+
+unsafe fn get_mic(
+    ctx: gss_ctx_id_t,
+    qop_req: OM_uint32,
+    msg: &[u8],
+) -> Result<Buf, Error> {
+    let mut minor = GSS_S_COMPLETE;
+    let mut msg = BufRef::from(msg);
+    let mut mic = Buf::empty();
+    let major = gss_get_mic(
+        &mut minor as *mut OM_uint32,
+        ctx,
+        qop_req,
+        msg.to_c(),
+        mic.to_c(),
+    );
+    if major == GSS_S_COMPLETE {
+        Ok(mic)
+    } else {
+        Err(Error {
+            major: MajorFlags::from_bits_retain(major),
+            minor,
+        })
+    }
+}
+
+unsafe fn verify_mic(
+    ctx: gss_ctx_id_t,
+    msg: &[u8],
+    mic: &[u8],
+) -> Result<OM_uint32, Error> {
+    let mut minor = GSS_S_COMPLETE;
+    let mut msg = BufRef::from(msg);
+    let mut mic = BufRef::from(mic);
+    let mut qop_state = GSS_C_QOP_DEFAULT;
+    let major = gss_verify_mic(
+        &mut minor as *mut OM_uint32,
+        ctx,
+        msg.to_c(),
+        mic.to_c(),
+        &mut qop_state as *mut OM_uint32,
+    );
+    if major == GSS_S_COMPLETE {
+        Ok(qop_state)
+    } else {
+        Err(Error {
+            major: MajorFlags::from_bits_retain(major),
+            minor,
+        })
+    }
+}
+
+unsafe fn process_token(ctx: gss_ctx_id_t, tok: &[u8]) -> Result<(), Error> {
+    let mut minor = GSS_S_COMPLETE;
+    let mut tok = BufRef::from(tok);
+    let major = gss_process_context_token(&mut minor as *mut OM_uint32, ctx, tok.to_c());
+    if major == GSS_S_COMPLETE {
+        Ok(())
+    } else {
+        Err(Error {
+            major: MajorFlags::from_bits_retain(major),
+            minor,
+        })
+    }
+}
+
+#[cfg(feature = "inquire")]
+unsafe fn inquire_by_oid(ctx: gss_ctx_id_t, oid: &Oid) -> Result<Vec<SecretBuf>, Error> {
+    let mut minor = GSS_S_COMPLETE;
+    let mut out = BufferSet::empty();
+    let major = gss_inquire_sec_context_by_oid(
+        &mut minor as *mut OM_uint32,
+        ctx,
+        oid.to_c(),
+        out.to_c(),
+    );
+    if major == GSS_S_COMPLETE {
+        Ok(out.into_iter().map(SecretBuf::from).collect())
+    } else {
+        Err(Error {
+            major: MajorFlags::from_bits_retain(major),
+            minor,
+        })
+    }
+}
+
+#[cfg(feature = "options")]
+unsafe fn set_option(
+    ctx: &mut gss_ctx_id_t,
+    desired: &Oid,
+    value: &[u8],
+) -> Result<(), Error> {
+    let mut minor = GSS_S_COMPLETE;
+    let mut value = BufRef::from(value);
+    let major = gss_set_sec_context_option(
+        &mut minor as *mut OM_uint32,
+        ctx as *mut gss_ctx_id_t,
+        desired.to_c(),
+        value.to_c(),
     );
     if major == GSS_S_COMPLETE {
-        Ok(out)
+        Ok(())
     } else {
         Err(Error {
             major: MajorFlags::from_bits_retain(major),
@@ -148,6 +399,188 @@ unsafe fn unwrap(ctx: gss_ctx_id_t, msg: &[u8]) -> Result<Buf, Error> {
     }
 }
 
+/// A key extracted from a krb5 lucid context. The key is zeroed
+/// when dropped.
+#[cfg(all(feature = "krb5", gssapi_mit))]
+pub struct LucidKey {
+    /// The krb5 enctype of the key, e.g. 18 for aes256-cts-hmac-sha1-96
+    pub enctype: u32,
+    key: Vec<u8>,
+}
+
+#[cfg(all(feature = "krb5", gssapi_mit))]
+impl fmt::Debug for LucidKey {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "LucidKey({}, <{} bytes>)", self.enctype, self.key.len())
+    }
+}
+
+#[cfg(all(feature = "krb5", gssapi_mit))]
+impl Drop for LucidKey {
+    fn drop(&mut self) {
+        zeroize(&mut self.key)
+    }
+}
+
+#[cfg(all(feature = "krb5", gssapi_mit))]
+impl LucidKey {
+    unsafe fn from_c(k: &gss_krb5_lucid_key_t) -> Self {
+        let key = if k.data.is_null() {
+            Vec::new()
+        } else {
+            slice::from_raw_parts(k.data as *const u8, k.length as usize).to_vec()
+        };
+        LucidKey {
+            enctype: k.type_,
+            key,
+        }
+    }
+
+    /// The raw key
+    pub fn key(&self) -> &[u8] {
+        &self.key
+    }
+}
+
+/// The keys of a krb5 lucid context, depending on which per message
+/// token format the context uses.
+#[cfg(all(feature = "krb5", gssapi_mit))]
+#[derive(Debug)]
+pub enum LucidKeys {
+    /// RFC 1964 tokens, used with the legacy DES and RC4 enctypes
+    Rfc1964 {
+        sign_alg: u32,
+        seal_alg: u32,
+        ctx_key: LucidKey,
+    },
+    /// RFC 4121 tokens, used with AES and newer enctypes
+    Cfx {
+        ctx_key: LucidKey,
+        acceptor_subkey: Option<LucidKey>,
+    },
+}
+
+/// The state of a krb5 context in the form other implementations of
+/// the per message tokens (e.g. a kernel) can use. See
+/// `SecurityContext::export_lucid`, which is only available with MIT
+/// Kerberos.
+#[cfg(all(feature = "krb5", gssapi_mit))]
+#[derive(Debug)]
+pub struct LucidContext {
+    pub version: u32,
+    /// True if this side initiated the context
+    pub initiate: bool,
+    /// When the context expires, in seconds since the unix epoch
+    pub endtime: u32,
+    pub send_seq: u64,
+    pub recv_seq: u64,
+    pub keys: LucidKeys,
+}
+
+#[cfg(all(feature = "krb5", gssapi_mit))]
+impl LucidContext {
+    unsafe fn from_c(c: &gss_krb5_lucid_context_v1_t) -> Self {
+        let keys = if c.protocol == 0 {
+            LucidKeys::Rfc1964 {
+                sign_alg: c.rfc1964_kd.sign_alg,
+                seal_alg: c.rfc1964_kd.seal_alg,
+                ctx_key: LucidKey::from_c(&c.rfc1964_kd.ctx_key),
+            }
+        } else {
+            LucidKeys::Cfx {
+                ctx_key: LucidKey::from_c(&c.cfx_kd.ctx_key),
+                acceptor_subkey: if c.cfx_kd.have_acceptor_subkey != 0 {
+                    Some(LucidKey::from_c(&c.cfx_kd.acceptor_subkey))
+                } else {
+                    None
+                },
+            }
+        };
+        LucidContext {
+            version: c.version,
+            initiate: c.initiate != 0,
+            endtime: c.endtime,
+            send_seq: c.send_seq,
+            recv_seq: c.recv_seq,
+            keys,
+        }
+    }
+}
+
+/// Export `ctx` as a lucid context. MIT deletes the context and nulls
+/// `ctx` on success, other implementations leave it alone, so the
+/// caller must be prepared for either.
+#[cfg(all(feature = "krb5", gssapi_mit))]
+unsafe fn export_lucid(
+    ctx: &mut gss_ctx_id_t,
+    version: u32,
+) -> Result<LucidContext, Error> {
+    // only version 1 is defined, and it's the only layout we can parse
+    if version != 1 {
+        return Err(Error {
+            major: MajorFlags::GSS_S_UNAVAILABLE,
+            minor: 0,
+        });
+    }
+    let mut minor = GSS_S_COMPLETE;
+    let mut lctx = ptr::null_mut::<ffi::c_void>();
+    let major = gss_krb5_export_lucid_sec_context(
+        &mut minor as *mut OM_uint32,
+        ctx as *mut gss_ctx_id_t,
+        version,
+        &mut lctx as *mut *mut ffi::c_void,
+    );
+    if major != GSS_S_COMPLETE {
+        return Err(Error {
+            major: MajorFlags::from_bits_retain(major),
+            minor,
+        });
+    }
+    let res = LucidContext::from_c(&*(lctx as *const gss_krb5_lucid_context_v1_t));
+    let mut _minor = GSS_S_COMPLETE;
+    let _major = gss_krb5_free_lucid_sec_context(&mut _minor as *mut OM_uint32, lctx);
+    Ok(res)
+}
+
+// Non default qops that have been defined by some mechanism. The
+// krb5 mechanism historically understood these (see RFC 1964, and
+// heimdal's gssapi_krb5.h), most modern mechanisms support only the
+// default.
+const KNOWN_QOPS: [OM_uint32; 2] = [
+    0x0100, // GSS_KRB5_CONF_C_QOP_DES
+    0x0200, // GSS_KRB5_CONF_C_QOP_DES3_KD
+];
+
+unsafe fn supported_qops(ctx: gss_ctx_id_t) -> Result<Vec<OM_uint32>, Error> {
+    let mut qops = Vec::new();
+    // probing the default first makes an unusable context an error
+    for qop in [GSS_C_QOP_DEFAULT].iter().chain(KNOWN_QOPS.iter()) {
+        let mut minor = GSS_S_COMPLETE;
+        let mut max_input: OM_uint32 = 0;
+        // the mechanism will reject a qop it doesn't support with
+        // GSS_S_BAD_QOP, we don't actually care about the size.
+        let major = gss_wrap_size_limit(
+            &mut minor as *mut OM_uint32,
+            ctx,
+            1,
+            *qop,
+            u16::MAX as OM_uint32,
+            &mut max_input as *mut OM_uint32,
+        );
+        if major == GSS_S_COMPLETE {
+            qops.push(*qop);
+        } else if *qop == GSS_C_QOP_DEFAULT
+            || gss_error(major) != MajorFlags::GSS_S_BAD_QOP.bits()
+        {
+            return Err(Error {
+                major: MajorFlags::from_bits_retain(major),
+                minor,
+            });
+        }
+    }
+    Ok(qops)
+}
+
 #[cfg(feature = "iov")]
 unsafe fn unwrap_iov(ctx: gss_ctx_id_t, msg: &mut [GssIov]) -> Result<(), Error> {
     let mut minor = GSS_S_COMPLETE;
@@ -173,11 +606,13 @@ unsafe fn unwrap_iov(ctx: gss_ctx_id_t, msg: &mut [GssIov]) -> Result<(), Error>
 pub struct CtxInfo {
     pub source_name: Name,
     pub target_name: Name,
-    pub lifetime: Duration,
+    pub lifetime: Lifetime,
     pub mechanism: &'static Oid,
     pub flags: CtxFlags,
     pub local: bool,
     pub open: bool,
+    /// True if the initiator authenticated anonymously
+    pub anonymous: bool,
 }
 
 struct CtxInfoC {
@@ -268,14 +703,16 @@ unsafe fn full_info(ctx: gss_ctx_id_t) -> Result<CtxInfo, Error> {
             open: Some(0),
         },
     )?;
+    let flags = CtxFlags::from_bits_retain(c.flags.unwrap());
     Ok(CtxInfo {
         source_name: Name::from_c(c.source_name.unwrap()),
         target_name: Name::from_c(c.target_name.unwrap()),
-        lifetime: Duration::from_secs(c.lifetime.unwrap() as u64),
+        lifetime: Lifetime::from_c(c.lifetime.unwrap()),
         mechanism: Oid::from_c(c.mechanism.unwrap()),
-        flags: CtxFlags::from_bits_retain(c.flags.unwrap()),
+        flags,
         local: c.local.unwrap() > 0,
         open: c.open.unwrap() > 0,
+        anonymous: flags.contains(CtxFlags::GSS_C_ANON_FLAG),
     })
 }
 
@@ -301,7 +738,7 @@ unsafe fn target_name(ctx: gss_ctx_id_t) -> Result<Name, Error> {
     Ok(Name::from_c(c.target_name.unwrap()))
 }
 
-unsafe fn lifetime(ctx: gss_ctx_id_t) -> Result<Duration, Error> {
+unsafe fn lifetime(ctx: gss_ctx_id_t) -> Result<Lifetime, Error> {
     let c = info(
         ctx,
         CtxInfoC {
@@ -309,7 +746,25 @@ unsafe fn lifetime(ctx: gss_ctx_id_t) -> Result<Duration, Error> {
             ..CtxInfoC::empty()
         },
     )?;
-    Ok(Duration::from_secs(c.lifetime.unwrap() as u64))
+    Ok(Lifetime::from_c(c.lifetime.unwrap()))
+}
+
+unsafe fn time_remaining(ctx: gss_ctx_id_t) -> Result<Lifetime, Error> {
+    let mut minor = GSS_S_COMPLETE;
+    let mut time_rec: OM_uint32 = 0;
+    let major = gss_context_time(
+        &mut minor as *mut OM_uint32,
+        ctx,
+        &mut time_rec as *mut OM_uint32,
+    );
+    if major == GSS_S_COMPLETE {
+        Ok(Lifetime::from_c(time_rec))
+    } else {
+        Err(Error {
+            major: MajorFlags::from_bits_retain(major),
+            minor,
+        })
+    }
 }
 
 unsafe fn mechanism(ctx: gss_ctx_id_t) -> Result<&'static Oid, Error> {
@@ -360,8 +815,23 @@ pub trait SecurityContext {
     /// Wrap a message with optional encryption. If `encrypt` is true
     /// then only the other side of the context can read the
     /// message. In any case the other side can always verify message
-    /// integrity.
-    fn wrap(&mut self, encrypt: bool, msg: &[u8]) -> Result<Buf, Error>;
+    /// integrity. Uses the default quality of protection.
+    fn wrap(&mut self, encrypt: bool, msg: &[u8]) -> Result<Buf, Error> {
+        Ok(self.wrap_qop(encrypt, GSS_C_QOP_DEFAULT, msg)?.0)
+    }
+
+    /// Wrap a message with optional encryption, requesting the
+    /// mechanism specific quality of protection `qop_req`. See
+    /// `supported_qops`. Along with the wrapped message returns
+    /// whether confidentiality was actually applied, which may be
+    /// false even if `encrypt` is true if the mechanism can only
+    /// provide integrity.
+    fn wrap_qop(
+        &mut self,
+        encrypt: bool,
+        qop_req: u32,
+        msg: &[u8],
+    ) -> Result<(Buf, bool), Error>;
 
     /** From the MIT kerberos documentation,
 
@@ -412,7 +882,9 @@ pub trait SecurityContext {
     /// This will set the required length of all the buffers except
     /// the data buffer, which must be provided as it will be to
     /// wrap_iov. The value of the encrypt flag must match what you
-    /// pass to `wrap_iov`.
+    /// pass to `wrap_iov`. If the mechanism doesn't support iovs this
+    /// fails with an error for which `Error::is_unsupported` is true,
+    /// use `wrap` instead.
     #[cfg(feature = "iov")]
     fn wrap_iov_length(
         &mut self,
@@ -421,8 +893,25 @@ pub trait SecurityContext {
     ) -> Result<(), Error>;
 
     /// Unwrap a wrapped message, checking it's integrity and
-    /// decrypting it if necessary.
-    fn unwrap(&mut self, msg: &[u8]) -> Result<Buf, Error>;
+    /// decrypting it if necessary. A replayed or out of sequence
+    /// message is an error carrying the status gssapi reported, see
+    /// `Error::token_status`. Use `unwrap_full` to get the message
+    /// anyway.
+    fn unwrap(&mut self, msg: &[u8]) -> Result<Buf, Error> {
+        let unwrapped = self.unwrap_full(msg)?;
+        match unwrapped.token_status {
+            None => Ok(unwrapped.msg),
+            Some(_) => Err(unwrapped.status),
+        }
+    }
+
+    /// Unwrap a wrapped message like `unwrap`, and also return how it
+    /// was protected, e.g. whether it was actually encrypted and the
+    /// quality of protection the sender used, so downgrades can be
+    /// detected. Unlike `unwrap` a replayed or out of sequence message
+    /// isn't an error, it is returned with `token_status` set so the
+    /// application can decide what to do with it.
+    fn unwrap_full(&mut self, msg: &[u8]) -> Result<Unwrapped, Error>;
 
     /** From the MIT Kerberos documentation,
 
@@ -443,6 +932,77 @@ pub trait SecurityContext {
     #[cfg(feature = "iov")]
     fn unwrap_iov(&mut self, msg: &mut [GssIov]) -> Result<(), Error>;
 
+    /// Compute a message integrity code (a signature) for `msg`. The
+    /// message itself is not included in the returned token, the
+    /// other side must receive both and check them with
+    /// `verify_mic`. Uses the default quality of protection.
+    fn get_mic(&mut self, msg: &[u8]) -> Result<Buf, Error> {
+        self.get_mic_qop(GSS_C_QOP_DEFAULT, msg)
+    }
+
+    /// Compute a message integrity code for `msg` with the mechanism
+    /// specific quality of protection `qop_req`.
+    fn get_mic_qop(&mut self, qop_req: u32, msg: &[u8]) -> Result<Buf, Error>;
+
+    /// Check that `mic` is a valid message integrity code for `msg`
+    /// produced by the other side of the context, and return the
+    /// quality of protection it was produced with. If verification
+    /// fails `Error::token_status` tells a bad signature apart from
+    /// a replayed or out of sequence token.
+    fn verify_mic(&mut self, msg: &[u8], mic: &[u8]) -> Result<u32, Error>;
+
+    /// Return the quality of protection values the mechanism will
+    /// accept for this context. This is best effort, gssapi has no
+    /// portable way of enumerating qops, so this probes a small list
+    /// of values known to be defined by some mechanism (currently only
+    /// the old krb5 ones). `GSS_C_QOP_DEFAULT` is always included. Fails
+    /// if the context isn't usable, e.g. because it isn't established
+    /// or has expired.
+    fn supported_qops(&self) -> Result<Vec<u32>, Error>;
+
+    /// Process a token the other side sent outside of context
+    /// establishment and message protection, e.g. the token produced
+    /// when it deletes its half of the context.
+    fn process_token(&mut self, tok: &[u8]) -> Result<(), Error>;
+
+    /// Delete the context, returning the token the other side should
+    /// be given (with `process_token`) to tear down its half if the
+    /// mechanism produces one. Dropping a context also deletes it, but
+    /// discards the token.
+    fn delete(self) -> Result<Option<Buf>, Error>
+    where
+        Self: Sized;
+
+    /// Ask the mechanism for the data identified by `oid`, e.g. the
+    /// session key with `GSS_INQ_SSPI_SESSION_KEY`. The supported oids
+    /// and the format of the returned buffers are specific to the
+    /// mechanism and implementation. Since the result may be key
+    /// material it is zeroed when dropped.
+    #[cfg(feature = "inquire")]
+    fn inquire_by_oid(&self, oid: &Oid) -> Result<Vec<SecretBuf>, Error>;
+
+    /// Set the mechanism specific option `desired` on the context to
+    /// `value`. The set of supported options, and the encoding of
+    /// their values, depends on the mechanism and the
+    /// implementation. MIT's krb5 mechanism doesn't support any
+    /// context options, Heimdal's supports a few. Unsupported options
+    /// fail with `GSS_S_UNAVAILABLE`.
+    #[cfg(feature = "options")]
+    fn set_option(&mut self, desired: &Oid, value: &[u8]) -> Result<(), Error>;
+
+    /// Export the context as a krb5 lucid context, the form in which
+    /// the sequence numbers and keys can be used by another
+    /// implementation of the per message tokens, e.g. to do RFC 4121
+    /// framing yourself. Only `version` 1 is defined. This consumes
+    /// the context, as the library gives up ownership of the key
+    /// material when exporting it. Fails with an error for which
+    /// `Error::is_unsupported` is true if the context isn't krb5 or
+    /// the version is unknown. Only available with MIT Kerberos.
+    #[cfg(all(feature = "krb5", gssapi_mit))]
+    fn export_lucid(self, version: u32) -> Result<LucidContext, Error>
+    where
+        Self: Sized;
+
     /// Get all information about a security context in one call
     fn info(&mut self) -> Result<CtxInfo, Error>;
 
@@ -453,11 +1013,22 @@ pub trait SecurityContext {
     fn target_name(&mut self) -> Result<Name, Error>;
 
     /// Get the lifetime of the security context
-    fn lifetime(&mut self) -> Result<Duration, Error>;
+    fn lifetime(&mut self) -> Result<Lifetime, Error>;
+
+    /// Get how much longer the security context is valid for. Fails
+    /// with `GSS_S_CONTEXT_EXPIRED` once it has expired, so a
+    /// connection can re-authenticate before that happens.
+    fn time_remaining(&self) -> Result<Lifetime, Error>;
 
     /// Get the mechanism of the security context
     fn mechanism(&mut self) -> Result<&'static Oid, Error>;
 
+    /// Get the concrete mechanism that was negotiated, e.g. krb5 when
+    /// SPNEGO was requested. This is remembered from context
+    /// establishment, so it is cheaper than `mechanism`, which is
+    /// only called if establishment hasn't reported one yet.
+    fn mech(&self) -> Result<Oid, Error>;
+
     /// Get the flags of the security context
     fn flags(&mut self) -> Result<CtxFlags, Error>;
 
@@ -469,6 +1040,125 @@ pub trait SecurityContext {
 
     /// Return true if the security context is fully initialized
     fn is_complete(&self) -> bool;
+
+    /// Return true if per message protection is available, either
+    /// because the context is complete, or because the mechanism
+    /// returned `GSS_C_PROT_READY_FLAG` during establishment. `wrap`,
+    /// `wrap_iov` and `get_mic` (and their variants) fail with
+    /// `GSS_S_NO_CONTEXT` until it is, so protected data can be sent
+    /// along with the final establishment token if the mechanism
+    /// allows it.
+    fn prot_ready(&self) -> bool;
+
+    /// The flags gssapi returned from the last successful step of
+    /// establishment, i.e. the services that will actually be
+    /// provided. On the client compare with
+    /// `ClientCtx::requested_flags` to find out what was
+    /// refused. Unlike `flags` this doesn't call into gssapi.
+    fn returned_flags(&self) -> CtxFlags;
+
+    /// Fail unless the context is complete and `GSS_C_MUTUAL_FLAG`
+    /// was returned. On a client this means the server authenticated
+    /// itself to us. On a server it only means the client asked us to
+    /// authenticate ourselves to it, the client is always
+    /// authenticated. A missing flag is reported as
+    /// `GSS_S_UNAUTHORIZED`, see `Error::is_unauthorized`, and an
+    /// incomplete context as `GSS_S_NO_CONTEXT`.
+    fn require_mutual_auth(&self) -> Result<(), Error> {
+        require_flags(self, CtxFlags::GSS_C_MUTUAL_FLAG)
+    }
+
+    /// Fail unless the context is complete and messages can be
+    /// encrypted (`GSS_C_CONF_FLAG` was returned). See
+    /// `require_mutual_auth`.
+    fn require_confidentiality(&self) -> Result<(), Error> {
+        require_flags(self, CtxFlags::GSS_C_CONF_FLAG)
+    }
+
+    /// Fail unless the context is complete and messages can be
+    /// integrity protected (`GSS_C_INTEG_FLAG` was returned). See
+    /// `require_mutual_auth`.
+    fn require_integrity(&self) -> Result<(), Error> {
+        require_flags(self, CtxFlags::GSS_C_INTEG_FLAG)
+    }
+}
+
+fn require_flags<C: SecurityContext + ?Sized>(
+    ctx: &C,
+    required: CtxFlags,
+) -> Result<(), Error> {
+    if !ctx.is_complete() {
+        Err(Error {
+            major: MajorFlags::GSS_S_NO_CONTEXT,
+            minor: 0,
+        })
+    } else if !ctx.returned_flags().contains(required) {
+        Err(Error {
+            major: MajorFlags::GSS_S_UNAUTHORIZED,
+            minor: 0,
+        })
+    } else {
+        Ok(())
+    }
+}
+
+fn check_prot_ready(ready: bool) -> Result<(), Error> {
+    if ready {
+        Ok(())
+    } else {
+        Err(Error {
+            major: MajorFlags::GSS_S_NO_CONTEXT,
+            minor: 0,
+        })
+    }
+}
+
+/// The result of one step of context establishment. A token is
+/// never empty, if gssapi produces an empty token it is reported as
+/// `None`.
+#[derive(Debug)]
+pub enum Step {
+    /// The context isn't established yet. Send the token (if any) to
+    /// the peer, and call `step` again with its reply. If there is no
+    /// token there is nothing to send, just wait for the peer's next
+    /// token.
+    Continue(Option<Buf>),
+    /// The context is established on this side. If there is a token
+    /// it must still be sent to the peer, which needs it to finish.
+    Done(Option<Buf>),
+}
+
+impl Step {
+    fn new(continue_needed: bool, tok: Buf) -> Step {
+        let tok = if tok.len() > 0 { Some(tok) } else { None };
+        if continue_needed {
+            Step::Continue(tok)
+        } else {
+            Step::Done(tok)
+        }
+    }
+
+    /// True if the context is established on this side
+    pub fn is_done(&self) -> bool {
+        match self {
+            Step::Continue(_) => false,
+            Step::Done(_) => true,
+        }
+    }
+
+    /// The token to send to the peer, if there is one
+    pub fn token(&self) -> Option<&Buf> {
+        match self {
+            Step::Continue(tok) | Step::Done(tok) => tok.as_ref(),
+        }
+    }
+
+    /// Take the token to send to the peer, if there is one
+    pub fn into_token(self) -> Option<Buf> {
+        match self {
+            Step::Continue(tok) | Step::Done(tok) => tok,
+        }
+    }
 }
 
 #[derive(Debug)]
@@ -484,8 +1174,14 @@ enum ServerCtxState {
 pub struct ServerCtx {
     ctx: gss_ctx_id_t,
     cred: Option<Cred>,
+    client_name: Option<Name>,
     delegated_cred: Option<Cred>,
-    flags: CtxFlags,
+    capture_delegated_cred: bool,
+    expected_peer_name_type: Option<&'static Oid>,
+    channel_bindings: Option<ChannelBindings>,
+    actual_mech: Option<&'static Oid>,
+    ret_flags: CtxFlags,
+    time_rec: Option<Lifetime>,
     state: ServerCtxState,
 }
 
@@ -500,36 +1196,73 @@ unsafe impl Sync for ServerCtx {}
 
 impl ServerCtx {
     /// Create a new uninitialized server context with the specified
-    /// credentials. You must then call `step` until the context is
-    /// fully initialized. The mechanism is not specified because it
-    /// is dictated by the client.
-    pub fn new(cred: Option<Cred>) -> ServerCtx {
+    /// credentials, or the default acceptor credentials
+    /// (`GSS_C_NO_CREDENTIAL`) if `cred` is `None`, which can accept
+    /// as any principal in the default keytab. You must then call
+    /// `step` until the context is fully initialized. The mechanism is
+    /// not specified because it is dictated by the client.
+    pub fn new(cred: Option<&Cred>) -> ServerCtx {
         ServerCtx {
             ctx: ptr::null_mut(),
-            cred,
+            cred: cred.cloned(),
+            client_name: None,
             delegated_cred: None,
-            flags: CtxFlags::empty(),
+            capture_delegated_cred: true,
+            expected_peer_name_type: None,
+            channel_bindings: None,
+            actual_mech: None,
+            ret_flags: CtxFlags::empty(),
+            time_rec: None,
             state: ServerCtxState::Uninitialized,
         }
     }
 
+    /// Start building a server context, see `ServerCtxBuilder`
+    pub fn builder() -> ServerCtxBuilder {
+        ServerCtxBuilder::default()
+    }
+
+    fn check_peer_name_type(&self) -> Result<(), Error> {
+        match self.expected_peer_name_type {
+            None => Ok(()),
+            Some(expected) => {
+                let peer = unsafe { source_name(self.ctx)? };
+                if peer.kind()? == Some(expected) {
+                    Ok(())
+                } else {
+                    Err(Error {
+                        major: MajorFlags::GSS_S_BAD_NAMETYPE,
+                        minor: 0,
+                    })
+                }
+            }
+        }
+    }
+
     /// Perform 1 step in the initialization of the server context,
-    /// feeding it a token you received from the client. If
-    /// initialization is complete from the point of view of the
-    /// server then this will return Ok(None). Otherwise it will
-    /// return a token that needs to be sent to the client and fed to
-    /// `ClientCtx::step`.
-    pub fn step(&mut self, tok: &[u8]) -> Result<Option<Buf>, Error> {
+    /// feeding it a token you received from the client. Returns
+    /// `Step::Continue` with a token that needs to be sent to the
+    /// client and fed to `ClientCtx::step` if initialization isn't
+    /// finished, otherwise `Step::Done` with the final token for the
+    /// client, if the mechanism produced one.
+    pub fn step(&mut self, tok: &[u8]) -> Result<Step, Error> {
         match self.state {
             ServerCtxState::Uninitialized | ServerCtxState::Partial => (),
             ServerCtxState::Failed(e) => return Err(e),
-            ServerCtxState::Complete => return Ok(None),
+            ServerCtxState::Complete => return Ok(Step::Done(None)),
         }
         let mut minor = GSS_S_COMPLETE;
         let mut tok = BufRef::from(tok);
         let mut out_tok = Buf::empty();
         let mut delegated_cred = ptr::null_mut::<gss_cred_id_struct>();
+        let mut src_name = ptr::null_mut::<gss_name_struct>();
         let mut flag_bits: u32 = 0;
+        let mut time_rec: OM_uint32 = 0;
+        let mut actual_mech = ptr::null_mut::<gss_OID_desc>();
+        let mut cbs = self
+            .channel_bindings
+            .as_ref()
+            .map(|cb| unsafe { cb.to_c() });
         let major = unsafe {
             gss_accept_sec_context(
                 &mut minor as *mut OM_uint32,
@@ -539,15 +1272,33 @@ impl ServerCtx {
                     Some(cred) => cred.to_c(),
                 },
                 tok.to_c(),
-                ptr::null_mut::<gss_channel_bindings_struct>(),
-                ptr::null_mut::<gss_name_t>(),
-                ptr::null_mut::<gss_OID>(),
+                match cbs {
+                    None => ptr::null_mut::<gss_channel_bindings_struct>(),
+                    Some(ref mut cbs) => cbs as gss_channel_bindings_t,
+                },
+                &mut src_name as *mut gss_name_t,
+                &mut actual_mech as *mut gss_OID,
                 out_tok.to_c(),
                 &mut flag_bits as *mut OM_uint32,
-                ptr::null_mut::<OM_uint32>(),
-                &mut delegated_cred as *mut gss_cred_id_t,
+                &mut time_rec as *mut OM_uint32,
+                if self.capture_delegated_cred {
+                    &mut delegated_cred as *mut gss_cred_id_t
+                } else {
+                    ptr::null_mut::<gss_cred_id_t>()
+                },
             )
         };
+        if !actual_mech.is_null() {
+            self.actual_mech = Some(unsafe { Oid::from_c(actual_mech) });
+        }
+        if !src_name.is_null() {
+            // we own the returned name, replacing it frees the old one
+            self.client_name = Some(unsafe { Name::from_c(src_name) });
+        }
+        if gss_error(major) == 0 {
+            self.ret_flags = CtxFlags::from_bits_retain(flag_bits);
+            self.time_rec = Some(Lifetime::from_c(time_rec));
+        }
         if !delegated_cred.is_null() {
             match &self.delegated_cred {
                 None => unsafe {
@@ -560,9 +1311,6 @@ impl ServerCtx {
                 },
             }
         }
-        if let Some(new_flags) = CtxFlags::from_bits(flag_bits) {
-            self.flags.insert(new_flags);
-        }
         if gss_error(major) > 0 {
             let e = Error {
                 major: MajorFlags::from_bits_retain(major),
@@ -572,33 +1320,139 @@ impl ServerCtx {
             Err(e)
         } else if major & _GSS_S_CONTINUE_NEEDED > 0 {
             self.state = ServerCtxState::Partial;
-            Ok(Some(out_tok))
+            Ok(Step::new(true, out_tok))
+        } else if let Err(e) = self.check_peer_name_type() {
+            self.state = ServerCtxState::Failed(e);
+            Err(e)
         } else {
             self.state = ServerCtxState::Complete;
-            if out_tok.len() > 0 {
-                Ok(Some(out_tok))
-            } else {
-                Ok(None)
-            }
+            Ok(Step::new(false, out_tok))
         }
     }
 
+    /// The lifetime of the context reported by the last successful
+    /// `step`, `None` before the first one. See
+    /// `ClientCtx::granted_lifetime`.
+    pub fn granted_lifetime(&self) -> Option<Lifetime> {
+        self.time_rec
+    }
+
+    /// The name the client asserted, as soon as the mechanism reports
+    /// it, which may be before the context is complete. This is useful
+    /// for logging or rate limiting during a multi step exchange, but
+    /// the client isn't authenticated until the context is complete.
+    /// Returns a copy, `None` if the name isn't known yet.
+    pub fn client_name(&self) -> Option<Name> {
+        self.client_name.as_ref().and_then(|n| n.duplicate().ok())
+    }
+
+    /// True if the context is complete and bound to the channel
+    /// bindings the server passed, i.e. the client included matching
+    /// ones. If the client's bindings didn't match `step` fails with
+    /// `GSS_S_BAD_BINDINGS`, but a client that sent none at all may
+    /// still be accepted, so a server that requires channel binding
+    /// must check this. Only implementations that report
+    /// `GSS_C_CHANNEL_BOUND_FLAG` (MIT krb5 1.19 and later) ever
+    /// return true.
+    pub fn channel_bound(&self) -> bool {
+        self.is_complete() && self.ret_flags.contains(CtxFlags::GSS_C_CHANNEL_BOUND_FLAG)
+    }
+
+    /// The credentials the client delegated, if any. Use
+    /// `Cred::time_remaining` (or `Cred::info`) to decide whether they
+    /// are worth storing, a delegated krb5 ticket often has a much
+    /// shorter lifetime than the client's own.
     pub fn delegated_cred(&self) -> Option<&Cred> {
         self.delegated_cred.as_ref()
     }
 
+    /// Take ownership of the credentials the client delegated, see
+    /// `delegated_cred`
     pub fn take_delegated_cred(&mut self) -> Option<Cred> {
         self.delegated_cred.take()
     }
 }
 
-impl SecurityContext for ServerCtx {
-    fn wrap(&mut self, encrypt: bool, msg: &[u8]) -> Result<Buf, Error> {
-        unsafe { wrap(self.ctx, encrypt, msg) }
-    }
+/// Build a `ServerCtx`. Like `ServerCtx::new` delegated credentials
+/// are kept by default, see `capture_delegated_cred`.
+#[derive(Debug)]
+pub struct ServerCtxBuilder {
+    cred: Option<Cred>,
+    capture_delegated_cred: bool,
+    expected_peer_name_type: Option<&'static Oid>,
+    channel_bindings: Option<ChannelBindings>,
+}
+
+impl Default for ServerCtxBuilder {
+    fn default() -> Self {
+        ServerCtxBuilder {
+            cred: None,
+            capture_delegated_cred: true,
+            expected_peer_name_type: None,
+            channel_bindings: None,
+        }
+    }
+}
+
+impl ServerCtxBuilder {
+    /// Accept using `cred` instead of the default acceptor
+    /// credentials, which can accept as any principal in the default
+    /// keytab. This selects which service the context will
+    /// accept for when the process has credentials for several.
+    pub fn cred(mut self, cred: &Cred) -> Self {
+        self.cred = Some(cred.clone());
+        self
+    }
+
+    /// Keep any credentials the client delegates (the default), see
+    /// `ServerCtx::delegated_cred`. If false gssapi discards them.
+    pub fn capture_delegated_cred(mut self, capture: bool) -> Self {
+        self.capture_delegated_cred = capture;
+        self
+    }
+
+    /// Require the authenticated peer name to be of the specified
+    /// type (e.g. `GSS_NT_KRB5_PRINCIPAL`). Once the context is
+    /// established the type of the peer name is checked, and if it
+    /// doesn't match `step` will fail with `GSS_S_BAD_NAMETYPE`. This
+    /// guards authorization logic that assumes a particular kind of
+    /// name against surprises from the mechanism.
+    pub fn expected_peer_name_type(mut self, kind: &'static Oid) -> Self {
+        self.expected_peer_name_type = Some(kind);
+        self
+    }
+
+    /// Require the client to use matching channel bindings
+    pub fn channel_bindings(mut self, channel_bindings: ChannelBindings) -> Self {
+        self.channel_bindings = Some(channel_bindings);
+        self
+    }
+
+    /// Build the context. You must then call `step` with the tokens
+    /// from the client until it is initialized.
+    pub fn build(self) -> ServerCtx {
+        let mut ctx = ServerCtx::new(self.cred.as_ref());
+        ctx.capture_delegated_cred = self.capture_delegated_cred;
+        ctx.expected_peer_name_type = self.expected_peer_name_type;
+        ctx.channel_bindings = self.channel_bindings;
+        ctx
+    }
+}
+
+impl SecurityContext for ServerCtx {
+    fn wrap_qop(
+        &mut self,
+        encrypt: bool,
+        qop_req: u32,
+        msg: &[u8],
+    ) -> Result<(Buf, bool), Error> {
+        check_prot_ready(self.prot_ready())?;
+        unsafe { wrap(self.ctx, encrypt, qop_req, msg) }
+    }
 
     #[cfg(feature = "iov")]
     fn wrap_iov(&mut self, encrypt: bool, msg: &mut [GssIov]) -> Result<(), Error> {
+        check_prot_ready(self.prot_ready())?;
         unsafe { wrap_iov(self.ctx, encrypt, msg) }
     }
 
@@ -608,10 +1462,11 @@ impl SecurityContext for ServerCtx {
         encrypt: bool,
         msg: &mut [GssIovFake],
     ) -> Result<(), Error> {
+        check_prot_ready(self.prot_ready())?;
         unsafe { wrap_iov_length(self.ctx, encrypt, msg) }
     }
 
-    fn unwrap(&mut self, msg: &[u8]) -> Result<Buf, Error> {
+    fn unwrap_full(&mut self, msg: &[u8]) -> Result<Unwrapped, Error> {
         unsafe { unwrap(self.ctx, msg) }
     }
 
@@ -620,6 +1475,42 @@ impl SecurityContext for ServerCtx {
         unsafe { unwrap_iov(self.ctx, msg) }
     }
 
+    fn get_mic_qop(&mut self, qop_req: u32, msg: &[u8]) -> Result<Buf, Error> {
+        check_prot_ready(self.prot_ready())?;
+        unsafe { get_mic(self.ctx, qop_req, msg) }
+    }
+
+    fn verify_mic(&mut self, msg: &[u8], mic: &[u8]) -> Result<u32, Error> {
+        unsafe { verify_mic(self.ctx, msg, mic) }
+    }
+
+    fn supported_qops(&self) -> Result<Vec<u32>, Error> {
+        unsafe { supported_qops(self.ctx) }
+    }
+
+    fn process_token(&mut self, tok: &[u8]) -> Result<(), Error> {
+        unsafe { process_token(self.ctx, tok) }
+    }
+
+    #[cfg(feature = "inquire")]
+    fn inquire_by_oid(&self, oid: &Oid) -> Result<Vec<SecretBuf>, Error> {
+        unsafe { inquire_by_oid(self.ctx, oid) }
+    }
+
+    fn delete(mut self) -> Result<Option<Buf>, Error> {
+        unsafe { delete(&mut self.ctx) }
+    }
+
+    #[cfg(all(feature = "krb5", gssapi_mit))]
+    fn export_lucid(mut self, version: u32) -> Result<LucidContext, Error> {
+        unsafe { export_lucid(&mut self.ctx, version) }
+    }
+
+    #[cfg(feature = "options")]
+    fn set_option(&mut self, desired: &Oid, value: &[u8]) -> Result<(), Error> {
+        unsafe { set_option(&mut self.ctx, desired, value) }
+    }
+
     fn info(&mut self) -> Result<CtxInfo, Error> {
         unsafe { full_info(self.ctx) }
     }
@@ -632,14 +1523,25 @@ impl SecurityContext for ServerCtx {
         unsafe { target_name(self.ctx) }
     }
 
-    fn lifetime(&mut self) -> Result<Duration, Error> {
+    fn lifetime(&mut self) -> Result<Lifetime, Error> {
         unsafe { lifetime(self.ctx) }
     }
 
+    fn time_remaining(&self) -> Result<Lifetime, Error> {
+        unsafe { time_remaining(self.ctx) }
+    }
+
     fn mechanism(&mut self) -> Result<&'static Oid, Error> {
         unsafe { mechanism(self.ctx) }
     }
 
+    fn mech(&self) -> Result<Oid, Error> {
+        match self.actual_mech {
+            Some(mech) => Ok(*mech),
+            None => unsafe { mechanism(self.ctx).copied() },
+        }
+    }
+
     fn flags(&mut self) -> Result<CtxFlags, Error> {
         unsafe { flags(self.ctx) }
     }
@@ -660,6 +1562,20 @@ impl SecurityContext for ServerCtx {
             | ServerCtxState::Uninitialized => false,
         }
     }
+
+    fn prot_ready(&self) -> bool {
+        match self.state {
+            ServerCtxState::Complete => true,
+            ServerCtxState::Partial => {
+                self.ret_flags.contains(CtxFlags::GSS_C_PROT_READY_FLAG)
+            }
+            ServerCtxState::Failed(_) | ServerCtxState::Uninitialized => false,
+        }
+    }
+
+    fn returned_flags(&self) -> CtxFlags {
+        self.ret_flags
+    }
 }
 
 #[derive(Debug)]
@@ -677,8 +1593,13 @@ pub struct ClientCtx {
     cred: Option<Cred>,
     target: Name,
     flags: CtxFlags,
+    ret_flags: CtxFlags,
     state: ClientCtxState,
     mech: Option<&'static Oid>,
+    actual_mech: Option<&'static Oid>,
+    time_req: Lifetime,
+    time_rec: Option<Lifetime>,
+    channel_bindings: Option<ChannelBindings>,
 }
 
 impl Drop for ClientCtx {
@@ -694,72 +1615,74 @@ impl ClientCtx {
     /// Create a new uninitialized client security context using the
     /// specified credentials, targeting the service named by target,
     /// and optionally using a specific mechanism (otherwise gssapi
-    /// will pick a default for you). To finish initializing the
-    /// context you must call `step`.
+    /// will pick a default for you). If `cred` is `None` the default
+    /// initiator credentials (`GSS_C_NO_CREDENTIAL`) are used, i.e.
+    /// those in the default credential cache. To finish initializing
+    /// the context you must call `step`.
     pub fn new(
-        cred: Option<Cred>,
+        cred: Option<&Cred>,
         target: Name,
         flags: CtxFlags,
         mech: Option<&'static Oid>,
     ) -> ClientCtx {
         ClientCtx {
             ctx: ptr::null_mut(),
-            cred,
+            cred: cred.cloned(),
             target,
             flags,
+            ret_flags: CtxFlags::empty(),
             state: ClientCtxState::Uninitialized,
             mech,
+            actual_mech: None,
+            time_req: Lifetime::Indefinite,
+            time_rec: None,
+            channel_bindings: None,
         }
     }
 
+    /// Start building a client context, see `ClientCtxBuilder`
+    pub fn builder() -> ClientCtxBuilder {
+        ClientCtxBuilder::default()
+    }
+
     /// Perform 1 step in the initialization of the specfied security
     /// context. Since the client initiates context creation, the
     /// token will initially be None. If the connection uses channel
-    /// bindings, they are passed as the second argument.
+    /// bindings, they are passed as the second argument, otherwise any
+    /// channel bindings set with `ClientCtxBuilder` are used.
     ///
     /// As a result this step, GSSAPI will give you a token
     /// to send to the server. The server may send back a token, which
     /// you must feed to this function, and possibly get another token
     /// to send to the server. This will go on a mechanism specifiec
-    /// number of times until step returns `Ok(None)`. At that point
-    /// the context is fully initialized.
+    /// number of times until step returns `Step::Done`. At that point
+    /// the context is fully initialized, but if `Done` carries a
+    /// token it must still be sent to the server.
     pub fn step(
         &mut self,
         tok: Option<&[u8]>,
         channel_bindings: Option<&[u8]>,
-    ) -> Result<Option<Buf>, Error> {
-        #[inline]
-        fn empty_buffer() -> gss_buffer_desc {
-            gss_buffer_desc {
-                length: 0,
-                value: ptr::null_mut(),
-            }
-        }
+    ) -> Result<Step, Error> {
         match self.state {
             ClientCtxState::Uninitialized | ClientCtxState::Partial => (),
             ClientCtxState::Failed(e) => return Err(e),
-            ClientCtxState::Complete => return Ok(None),
+            ClientCtxState::Complete => return Ok(Step::Done(None)),
         };
-        let mut cbs = gss_channel_bindings_struct {
-            initiator_addrtype: 0,
-            initiator_address: empty_buffer(),
-            acceptor_addrtype: 0,
-            acceptor_address: empty_buffer(),
-            application_data: empty_buffer(),
-        };
-        let bindings = if let Some(cb) = channel_bindings {
-            cbs.application_data = gss_buffer_desc {
-                length: cb.len(),
-                value: cb.as_ptr() as *mut ffi::c_void,
-            };
-            &mut cbs as gss_channel_bindings_t
-        } else {
-            ptr::null_mut::<gss_channel_bindings_struct>()
+        let channel_bindings = channel_bindings.map(ChannelBindings::new);
+        let mut cbs = channel_bindings
+            .as_ref()
+            .or(self.channel_bindings.as_ref())
+            .map(|cb| unsafe { cb.to_c() });
+        let bindings = match cbs {
+            None => ptr::null_mut::<gss_channel_bindings_struct>(),
+            Some(ref mut cbs) => cbs as gss_channel_bindings_t,
         };
         let mut minor = GSS_S_COMPLETE;
         let mut tok = tok.map(BufRef::from);
         let mut out_tok = Buf::empty();
         let mut flag_bits: u32 = 0;
+        let mut time_rec: OM_uint32 = 0;
+        let mut actual_mech = ptr::null_mut::<gss_OID_desc>();
         let major = unsafe {
             gss_init_sec_context(
                 &mut minor as *mut OM_uint32,
@@ -774,20 +1697,24 @@ impl ClientCtx {
                     Some(mech) => mech.to_c(),
                 },
                 self.flags.bits(),
-                _GSS_C_INDEFINITE,
+                self.time_req.to_c(),
                 bindings,
                 match tok {
                     None => ptr::null_mut::<gss_buffer_desc>(),
                     Some(ref mut tok) => tok.to_c(),
                 },
-                ptr::null_mut::<gss_OID>(),
+                &mut actual_mech as *mut gss_OID,
                 out_tok.to_c(),
                 &mut flag_bits as *mut OM_uint32,
-                ptr::null_mut::<OM_uint32>(),
+                &mut time_rec as *mut OM_uint32,
             )
         };
-        if let Some(new_flags) = CtxFlags::from_bits(flag_bits) {
-            self.flags.insert(new_flags);
+        if gss_error(major) == 0 {
+            self.ret_flags = CtxFlags::from_bits_retain(flag_bits);
+            self.time_rec = Some(Lifetime::from_c(time_rec));
+        }
+        if !actual_mech.is_null() {
+            self.actual_mech = Some(unsafe { Oid::from_c(actual_mech) });
         }
         if gss_error(major) > 0 {
             let e = Error {
@@ -798,25 +1725,149 @@ impl ClientCtx {
             Err(e)
         } else if major & _GSS_S_CONTINUE_NEEDED > 0 {
             self.state = ClientCtxState::Partial;
-            Ok(Some(out_tok))
+            Ok(Step::new(true, out_tok))
         } else {
             self.state = ClientCtxState::Complete;
-            if out_tok.len() > 0 {
-                Ok(Some(out_tok))
-            } else {
-                Ok(None)
+            Ok(Step::new(false, out_tok))
+        }
+    }
+
+    /// The flags that were requested when the context was created
+    pub fn requested_flags(&self) -> CtxFlags {
+        self.flags
+    }
+
+    /// The lifetime of the context reported by the last successful
+    /// `step`, `None` before the first one. Once the context is
+    /// complete this is the lifetime that was granted, which may be
+    /// shorter than the one requested with
+    /// `ClientCtxBuilder::lifetime`, e.g. if the credentials expire
+    /// sooner.
+    pub fn granted_lifetime(&self) -> Option<Lifetime> {
+        self.time_rec
+    }
+
+    /// True if credentials were delegated to the server, i.e.
+    /// `GSS_C_DELEG_FLAG` is among the returned flags. This is the
+    /// case when either `GSS_C_DELEG_FLAG` was requested, or
+    /// `GSS_C_DELEG_POLICY_FLAG` was and the KDC marked the service
+    /// OK-AS-DELEGATE.
+    pub fn delegated(&self) -> bool {
+        self.ret_flags.contains(CtxFlags::GSS_C_DELEG_FLAG)
+    }
+
+    /// True if the context is complete, delegation was requested, but
+    /// no credentials were delegated, e.g. because
+    /// `GSS_C_DELEG_POLICY_FLAG` was requested and policy didn't allow
+    /// it.
+    pub fn delegation_suppressed(&self) -> bool {
+        let requested = self
+            .flags
+            .intersects(CtxFlags::GSS_C_DELEG_FLAG | CtxFlags::GSS_C_DELEG_POLICY_FLAG);
+        self.is_complete() && requested && !self.delegated()
+    }
+}
+
+/// Build a `ClientCtx` without a long list of positional
+/// arguments. Only the target name is required, everything else has
+/// the same default as the corresponding argument to `ClientCtx::new`.
+#[derive(Debug, Default)]
+pub struct ClientCtxBuilder {
+    cred: Option<Cred>,
+    target: Option<Name>,
+    flags: CtxFlags,
+    deleg_policy: bool,
+    mech: Option<&'static Oid>,
+    time_req: Lifetime,
+    channel_bindings: Option<ChannelBindings>,
+}
+
+impl ClientCtxBuilder {
+    /// Use `cred` instead of the default initiator credentials, those
+    /// in the default credential cache
+    pub fn cred(mut self, cred: &Cred) -> Self {
+        self.cred = Some(cred.clone());
+        self
+    }
+
+    /// The name of the service to authenticate to (required)
+    pub fn target(mut self, target: Name) -> Self {
+        self.target = Some(target);
+        self
+    }
+
+    /// The flags to request, by default none
+    pub fn flags(mut self, flags: CtxFlags) -> Self {
+        self.flags = flags;
+        self
+    }
+
+    /// Delegate credentials only if the KDC policy permits it, by
+    /// requesting `GSS_C_DELEG_POLICY_FLAG` in addition to `flags`. In
+    /// krb5 that means the service is marked OK-AS-DELEGATE. This is
+    /// the safe way to delegate, check `ClientCtx::delegated` once the
+    /// context is established to see if it happened.
+    pub fn delegate_by_policy(mut self, deleg_policy: bool) -> Self {
+        self.deleg_policy = deleg_policy;
+        self
+    }
+
+    /// Use a specific mechanism instead of the default
+    pub fn mech(mut self, mech: &'static Oid) -> Self {
+        self.mech = Some(mech);
+        self
+    }
+
+    /// Request a context that lasts `lifetime`, either a `Lifetime` or
+    /// a `Duration`, instead of as long as possible
+    pub fn lifetime(mut self, lifetime: impl Into<Lifetime>) -> Self {
+        self.time_req = lifetime.into();
+        self
+    }
+
+    /// Bind the context to the underlying channel
+    pub fn channel_bindings(mut self, channel_bindings: ChannelBindings) -> Self {
+        self.channel_bindings = Some(channel_bindings);
+        self
+    }
+
+    /// Build the context, failing with `GSS_S_BAD_NAME` if no target
+    /// was specified. You must then call `step` to initialize it.
+    pub fn build(self) -> Result<ClientCtx, Error> {
+        let target = match self.target {
+            Some(target) => target,
+            None => {
+                return Err(Error {
+                    major: MajorFlags::GSS_S_BAD_NAME,
+                    minor: 0,
+                })
             }
+        };
+        let mut flags = self.flags;
+        if self.deleg_policy {
+            flags.insert(CtxFlags::GSS_C_DELEG_POLICY_FLAG);
         }
+        let mut ctx = ClientCtx::new(self.cred.as_ref(), target, flags, self.mech);
+        ctx.time_req = self.time_req;
+        ctx.channel_bindings = self.channel_bindings;
+        Ok(ctx)
     }
 }
 
 impl SecurityContext for ClientCtx {
-    fn wrap(&mut self, encrypt: bool, msg: &[u8]) -> Result<Buf, Error> {
-        unsafe { wrap(self.ctx, encrypt, msg) }
+    fn wrap_qop(
+        &mut self,
+        encrypt: bool,
+        qop_req: u32,
+        msg: &[u8],
+    ) -> Result<(Buf, bool), Error> {
+        check_prot_ready(self.prot_ready())?;
+        unsafe { wrap(self.ctx, encrypt, qop_req, msg) }
     }
 
     #[cfg(feature = "iov")]
     fn wrap_iov(&mut self, encrypt: bool, msg: &mut [GssIov]) -> Result<(), Error> {
+        check_prot_ready(self.prot_ready())?;
         unsafe { wrap_iov(self.ctx, encrypt, msg) }
     }
 
@@ -826,10 +1877,11 @@ impl SecurityContext for ClientCtx {
         encrypt: bool,
         msg: &mut [GssIovFake],
     ) -> Result<(), Error> {
+        check_prot_ready(self.prot_ready())?;
         unsafe { wrap_iov_length(self.ctx, encrypt, msg) }
     }
 
-    fn unwrap(&mut self, msg: &[u8]) -> Result<Buf, Error> {
+    fn unwrap_full(&mut self, msg: &[u8]) -> Result<Unwrapped, Error> {
         unsafe { unwrap(self.ctx, msg) }
     }
 
@@ -838,6 +1890,42 @@ impl SecurityContext for ClientCtx {
         unsafe { unwrap_iov(self.ctx, msg) }
     }
 
+    fn get_mic_qop(&mut self, qop_req: u32, msg: &[u8]) -> Result<Buf, Error> {
+        check_prot_ready(self.prot_ready())?;
+        unsafe { get_mic(self.ctx, qop_req, msg) }
+    }
+
+    fn verify_mic(&mut self, msg: &[u8], mic: &[u8]) -> Result<u32, Error> {
+        unsafe { verify_mic(self.ctx, msg, mic) }
+    }
+
+    fn supported_qops(&self) -> Result<Vec<u32>, Error> {
+        unsafe { supported_qops(self.ctx) }
+    }
+
+    fn process_token(&mut self, tok: &[u8]) -> Result<(), Error> {
+        unsafe { process_token(self.ctx, tok) }
+    }
+
+    #[cfg(feature = "inquire")]
+    fn inquire_by_oid(&self, oid: &Oid) -> Result<Vec<SecretBuf>, Error> {
+        unsafe { inquire_by_oid(self.ctx, oid) }
+    }
+
+    fn delete(mut self) -> Result<Option<Buf>, Error> {
+        unsafe { delete(&mut self.ctx) }
+    }
+
+    #[cfg(all(feature = "krb5", gssapi_mit))]
+    fn export_lucid(mut self, version: u32) -> Result<LucidContext, Error> {
+        unsafe { export_lucid(&mut self.ctx, version) }
+    }
+
+    #[cfg(feature = "options")]
+    fn set_option(&mut self, desired: &Oid, value: &[u8]) -> Result<(), Error> {
+        unsafe { set_option(&mut self.ctx, desired, value) }
+    }
+
     fn info(&mut self) -> Result<CtxInfo, Error> {
         unsafe { full_info(self.ctx) }
     }
@@ -850,14 +1938,25 @@ impl SecurityContext for ClientCtx {
         unsafe { target_name(self.ctx) }
     }
 
-    fn lifetime(&mut self) -> Result<Duration, Error> {
+    fn lifetime(&mut self) -> Result<Lifetime, Error> {
         unsafe { lifetime(self.ctx) }
     }
 
+    fn time_remaining(&self) -> Result<Lifetime, Error> {
+        unsafe { time_remaining(self.ctx) }
+    }
+
     fn mechanism(&mut self) -> Result<&'static Oid, Error> {
         unsafe { mechanism(self.ctx) }
     }
 
+    fn mech(&self) -> Result<Oid, Error> {
+        match self.actual_mech {
+            Some(mech) => Ok(*mech),
+            None => unsafe { mechanism(self.ctx).copied() },
+        }
+    }
+
     fn flags(&mut self) -> Result<CtxFlags, Error> {
         unsafe { flags(self.ctx) }
     }
@@ -878,4 +1977,649 @@ impl SecurityContext for ClientCtx {
             | ClientCtxState::Uninitialized => false,
         }
     }
+
+    fn prot_ready(&self) -> bool {
+        match self.state {
+            ClientCtxState::Complete => true,
+            ClientCtxState::Partial => {
+                self.ret_flags.contains(CtxFlags::GSS_C_PROT_READY_FLAG)
+            }
+            ClientCtxState::Failed(_) | ClientCtxState::Uninitialized => false,
+        }
+    }
+
+    fn returned_flags(&self) -> CtxFlags {
+        self.ret_flags
+    }
+}
+
+/// Either side of a security context. Once a context is established
+/// the two sides are used in exactly the same way, so this lets
+/// transport code drive establishment and then protect messages
+/// without caring which role it is playing.
+#[derive(Debug)]
+pub enum Ctx {
+    Client(ClientCtx),
+    Server(ServerCtx),
+}
+
+impl From<ClientCtx> for Ctx {
+    fn from(ctx: ClientCtx) -> Self {
+        Ctx::Client(ctx)
+    }
+}
+
+impl From<ServerCtx> for Ctx {
+    fn from(ctx: ServerCtx) -> Self {
+        Ctx::Server(ctx)
+    }
+}
+
+impl Ctx {
+    fn raw(&self) -> gss_ctx_id_t {
+        match self {
+            Ctx::Client(ctx) => ctx.ctx,
+            Ctx::Server(ctx) => ctx.ctx,
+        }
+    }
+
+    /// Perform 1 step in the initialization of the context, whichever
+    /// side it is. The client starts with no token, the server must
+    /// always be given one, it fails with `GSS_S_DEFECTIVE_TOKEN`
+    /// without. See `ClientCtx::step` and `ServerCtx::step`.
+    pub fn step(&mut self, tok: Option<&[u8]>) -> Result<Step, Error> {
+        match self {
+            Ctx::Client(ctx) => ctx.step(tok, None),
+            Ctx::Server(ctx) => match tok {
+                Some(tok) => ctx.step(tok),
+                None => Err(Error {
+                    major: MajorFlags::GSS_S_DEFECTIVE_TOKEN,
+                    minor: 0,
+                }),
+            },
+        }
+    }
+
+    /// The lifetime reported by the last successful `step`, see
+    /// `ClientCtx::granted_lifetime`
+    pub fn granted_lifetime(&self) -> Option<Lifetime> {
+        match self {
+            Ctx::Client(ctx) => ctx.granted_lifetime(),
+            Ctx::Server(ctx) => ctx.granted_lifetime(),
+        }
+    }
+}
+
+impl SecurityContext for Ctx {
+    fn wrap_qop(
+        &mut self,
+        encrypt: bool,
+        qop_req: u32,
+        msg: &[u8],
+    ) -> Result<(Buf, bool), Error> {
+        check_prot_ready(self.prot_ready())?;
+        unsafe { wrap(self.raw(), encrypt, qop_req, msg) }
+    }
+
+    #[cfg(feature = "iov")]
+    fn wrap_iov(&mut self, encrypt: bool, msg: &mut [GssIov]) -> Result<(), Error> {
+        check_prot_ready(self.prot_ready())?;
+        unsafe { wrap_iov(self.raw(), encrypt, msg) }
+    }
+
+    #[cfg(feature = "iov")]
+    fn wrap_iov_length(
+        &mut self,
+        encrypt: bool,
+        msg: &mut [GssIovFake],
+    ) -> Result<(), Error> {
+        check_prot_ready(self.prot_ready())?;
+        unsafe { wrap_iov_length(self.raw(), encrypt, msg) }
+    }
+
+    fn unwrap_full(&mut self, msg: &[u8]) -> Result<Unwrapped, Error> {
+        unsafe { unwrap(self.raw(), msg) }
+    }
+
+    #[cfg(feature = "iov")]
+    fn unwrap_iov(&mut self, msg: &mut [GssIov]) -> Result<(), Error> {
+        unsafe { unwrap_iov(self.raw(), msg) }
+    }
+
+    fn get_mic_qop(&mut self, qop_req: u32, msg: &[u8]) -> Result<Buf, Error> {
+        check_prot_ready(self.prot_ready())?;
+        unsafe { get_mic(self.raw(), qop_req, msg) }
+    }
+
+    fn verify_mic(&mut self, msg: &[u8], mic: &[u8]) -> Result<u32, Error> {
+        unsafe { verify_mic(self.raw(), msg, mic) }
+    }
+
+    fn supported_qops(&self) -> Result<Vec<u32>, Error> {
+        unsafe { supported_qops(self.raw()) }
+    }
+
+    fn process_token(&mut self, tok: &[u8]) -> Result<(), Error> {
+        unsafe { process_token(self.raw(), tok) }
+    }
+
+    #[cfg(feature = "inquire")]
+    fn inquire_by_oid(&self, oid: &Oid) -> Result<Vec<SecretBuf>, Error> {
+        unsafe { inquire_by_oid(self.raw(), oid) }
+    }
+
+    fn delete(self) -> Result<Option<Buf>, Error> {
+        match self {
+            Ctx::Client(ctx) => ctx.delete(),
+            Ctx::Server(ctx) => ctx.delete(),
+        }
+    }
+
+    #[cfg(all(feature = "krb5", gssapi_mit))]
+    fn export_lucid(self, version: u32) -> Result<LucidContext, Error> {
+        match self {
+            Ctx::Client(ctx) => ctx.export_lucid(version),
+            Ctx::Server(ctx) => ctx.export_lucid(version),
+        }
+    }
+
+    #[cfg(feature = "options")]
+    fn set_option(&mut self, desired: &Oid, value: &[u8]) -> Result<(), Error> {
+        let ctx = match self {
+            Ctx::Client(ctx) => &mut ctx.ctx,
+            Ctx::Server(ctx) => &mut ctx.ctx,
+        };
+        unsafe { set_option(ctx, desired, value) }
+    }
+
+    fn info(&mut self) -> Result<CtxInfo, Error> {
+        unsafe { full_info(self.raw()) }
+    }
+
+    fn source_name(&mut self) -> Result<Name, Error> {
+        unsafe { source_name(self.raw()) }
+    }
+
+    fn target_name(&mut self) -> Result<Name, Error> {
+        unsafe { target_name(self.raw()) }
+    }
+
+    fn lifetime(&mut self) -> Result<Lifetime, Error> {
+        unsafe { lifetime(self.raw()) }
+    }
+
+    fn time_remaining(&self) -> Result<Lifetime, Error> {
+        unsafe { time_remaining(self.raw()) }
+    }
+
+    fn mechanism(&mut self) -> Result<&'static Oid, Error> {
+        unsafe { mechanism(self.raw()) }
+    }
+
+    fn mech(&self) -> Result<Oid, Error> {
+        match self {
+            Ctx::Client(ctx) => ctx.mech(),
+            Ctx::Server(ctx) => ctx.mech(),
+        }
+    }
+
+    fn flags(&mut self) -> Result<CtxFlags, Error> {
+        unsafe { flags(self.raw()) }
+    }
+
+    fn local(&mut self) -> Result<bool, Error> {
+        unsafe { local(self.raw()) }
+    }
+
+    fn open(&mut self) -> Result<bool, Error> {
+        unsafe { open(self.raw()) }
+    }
+
+    fn is_complete(&self) -> bool {
+        match self {
+            Ctx::Client(ctx) => ctx.is_complete(),
+            Ctx::Server(ctx) => ctx.is_complete(),
+        }
+    }
+
+    fn prot_ready(&self) -> bool {
+        match self {
+            Ctx::Client(ctx) => ctx.prot_ready(),
+            Ctx::Server(ctx) => ctx.prot_ready(),
+        }
+    }
+
+    fn returned_flags(&self) -> CtxFlags {
+        match self {
+            Ctx::Client(ctx) => ctx.returned_flags(),
+            Ctx::Server(ctx) => ctx.returned_flags(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        credential::CredUsage,
+        oid::{
+            GSS_MECH_KRB5, GSS_NT_EXPORT_NAME, GSS_NT_HOSTBASED_SERVICE,
+            GSS_NT_KRB5_PRINCIPAL,
+        },
+    };
+    use std::time::Duration;
+
+    fn host_name() -> Name {
+        Name::new(b"host", Some(&GSS_NT_HOSTBASED_SERVICE))
+            .expect("Failed to import service name")
+            .canonicalize(Some(&GSS_MECH_KRB5))
+            .expect("Failed to canonicalize service name")
+    }
+
+    fn setup() -> (Ctx, Ctx) {
+        setup_with(|server| server)
+    }
+
+    fn setup_with(f: impl FnOnce(ServerCtxBuilder) -> ServerCtxBuilder) -> (Ctx, Ctx) {
+        let cname = host_name();
+        let server_cred =
+            Cred::acquire(Some(&cname), Lifetime::Indefinite, CredUsage::Accept, None)
+                .expect("Failed to acquire acceptor credential");
+        let server = f(ServerCtx::builder().cred(&server_cred)).build();
+        let flags = CtxFlags::GSS_C_MUTUAL_FLAG | CtxFlags::GSS_C_REPLAY_FLAG;
+        let client = ClientCtx::new(None, cname, flags, Some(&GSS_MECH_KRB5));
+        (client.into(), server.into())
+    }
+
+    fn establish(client: &mut Ctx, server: &mut Ctx) {
+        let mut tok: Option<Buf> = None;
+        let sides = [client, server];
+        let mut i = 0;
+        loop {
+            match sides[i % 2].step(tok.as_deref()).expect("Failed to step context") {
+                Step::Done(None) => break,
+                Step::Continue(t) | Step::Done(t) => tok = t,
+            }
+            i += 1;
+        }
+        assert!(sides[0].is_complete() || sides[1].is_complete());
+    }
+
+    fn round_trip<A: SecurityContext, B: SecurityContext>(a: &mut A, b: &mut B) {
+        let msg = b"super secret message";
+        let wrapped = a.wrap(true, msg).expect("Failed to wrap message");
+        let unwrapped = b.unwrap(&wrapped).expect("Failed to unwrap message");
+        assert_eq!(&*unwrapped, msg);
+        let mic = a.get_mic(msg).expect("Failed to get mic");
+        let qop = b.verify_mic(msg, &mic).expect("Failed to verify mic");
+        assert_eq!(qop, GSS_C_QOP_DEFAULT);
+        let e = b
+            .verify_mic(b"tampered message", &mic)
+            .expect_err("Expected error verifying a mic for another message");
+        assert_eq!(e.token_status(), Some(TokenStatus::BadSig));
+        let (wrapped, conf_state) = a
+            .wrap_qop(true, GSS_C_QOP_DEFAULT, msg)
+            .expect("Failed to wrap message");
+        assert!(conf_state);
+        let unwrapped = b.unwrap_full(&wrapped).expect("Failed to unwrap message");
+        assert_eq!(&*unwrapped.msg, msg);
+        assert!(unwrapped.conf_state);
+        assert_eq!(unwrapped.qop_state, GSS_C_QOP_DEFAULT);
+        let (wrapped, conf_state) = a
+            .wrap_qop(false, GSS_C_QOP_DEFAULT, msg)
+            .expect("Failed to wrap message");
+        assert!(!conf_state);
+        let unwrapped = b.unwrap_full(&wrapped).expect("Failed to unwrap message");
+        assert!(!unwrapped.conf_state);
+        assert_eq!(unwrapped.token_status, None);
+        if b.flags().expect("Failed to get flags").contains(CtxFlags::GSS_C_REPLAY_FLAG) {
+            let replayed = b.unwrap_full(&wrapped).expect("Failed to unwrap message");
+            assert_eq!(replayed.token_status, Some(TokenStatus::Duplicate));
+            let e = b.unwrap(&wrapped).expect_err("Expected error unwrapping a replay");
+            assert_eq!(e.token_status(), Some(TokenStatus::Duplicate));
+            assert_eq!(e.major, replayed.status.major);
+            assert_eq!(e.minor, replayed.status.minor);
+        }
+        let mic = a
+            .get_mic_qop(GSS_C_QOP_DEFAULT, msg)
+            .expect("Failed to get mic");
+        let qop = b.verify_mic(msg, &mic).expect("Failed to verify mic");
+        assert_eq!(qop, GSS_C_QOP_DEFAULT);
+    }
+
+    #[test]
+    fn test_unified_round_trip() {
+        let (mut client, mut server) = setup();
+        establish(&mut client, &mut server);
+        round_trip(&mut client, &mut server);
+        round_trip(&mut server, &mut client);
+        if let Ctx::Server(server) = &server {
+            let name = server.client_name().expect("Expected a client name");
+            assert_eq!(
+                name.export().expect("Failed to export name").to_vec(),
+                client.source_name().unwrap().export().unwrap().to_vec()
+            );
+        }
+        assert!(client.returned_flags().contains(CtxFlags::GSS_C_MUTUAL_FLAG));
+        assert!(server.returned_flags().contains(CtxFlags::GSS_C_MUTUAL_FLAG));
+        assert!(client.local().unwrap());
+        assert!(!server.local().unwrap());
+        assert_eq!(client.mech().expect("Failed to get mech"), GSS_MECH_KRB5);
+        assert_eq!(server.mech().expect("Failed to get mech"), GSS_MECH_KRB5);
+        match client.time_remaining().expect("Failed to get time remaining") {
+            Lifetime::Indefinite => (),
+            Lifetime::Seconds(secs) => assert!(secs > 0),
+        }
+        #[cfg(feature = "inquire")]
+        {
+            use crate::oid::GSS_INQ_SSPI_SESSION_KEY;
+            let ckey = client
+                .inquire_by_oid(&GSS_INQ_SSPI_SESSION_KEY)
+                .expect("Failed to inquire session key");
+            let skey = server
+                .inquire_by_oid(&GSS_INQ_SSPI_SESSION_KEY)
+                .expect("Failed to inquire session key");
+            assert!(!ckey.is_empty());
+            assert_eq!(&*ckey[0], &*skey[0]);
+        }
+        if let Some(tok) = client.delete().expect("Failed to delete context") {
+            server.process_token(&tok).expect("Failed to process delete token");
+        }
+    }
+
+    #[test]
+    fn test_default_creds() {
+        let flags = CtxFlags::GSS_C_MUTUAL_FLAG;
+        let client = ClientCtx::new(None, host_name(), flags, Some(&GSS_MECH_KRB5));
+        let mut client = Ctx::from(client);
+        let mut server = Ctx::from(ServerCtx::new(None));
+        establish(&mut client, &mut server);
+        round_trip(&mut client, &mut server);
+    }
+
+    #[test]
+    fn test_requested_lifetime() {
+        let client = ClientCtx::builder()
+            .target(host_name())
+            .mech(&GSS_MECH_KRB5)
+            .lifetime(Duration::from_secs(60))
+            .build()
+            .expect("Failed to build client context");
+        let mut client = Ctx::from(client);
+        let mut server = Ctx::from(ServerCtx::new(None));
+        assert_eq!(client.granted_lifetime(), None);
+        establish(&mut client, &mut server);
+        let short = |l: Lifetime| match l {
+            Lifetime::Indefinite => false,
+            Lifetime::Seconds(secs) => secs <= 60,
+        };
+        assert!(short(client.granted_lifetime().expect("Expected a lifetime")));
+        assert!(short(client.time_remaining().expect("Failed to get time remaining")));
+    }
+
+    #[test]
+    fn test_delegated_cred_lifetime() {
+        let flags = CtxFlags::GSS_C_MUTUAL_FLAG | CtxFlags::GSS_C_DELEG_FLAG;
+        let client = ClientCtx::new(None, host_name(), flags, Some(&GSS_MECH_KRB5));
+        let server = ServerCtx::new(None);
+        let (mut client, mut server) = (Ctx::from(client), Ctx::from(server));
+        establish(&mut client, &mut server);
+        let server = match server {
+            Ctx::Server(server) => server,
+            Ctx::Client(_) => unreachable!(),
+        };
+        let cred = server
+            .delegated_cred()
+            .expect("Expected delegated credentials, is the client's ticket forwardable?");
+        match cred.time_remaining().expect("Failed to get time remaining") {
+            Lifetime::Indefinite => (),
+            Lifetime::Seconds(secs) => assert!(secs > 0),
+        }
+        let info = cred.info().expect("Failed to get delegated cred info");
+        assert!(info.mechanisms.contains(&GSS_MECH_KRB5).unwrap());
+    }
+
+    #[test]
+    fn test_time_remaining_no_context() {
+        let ctx = Ctx::from(ServerCtx::new(None));
+        let e = ctx
+            .time_remaining()
+            .expect_err("Expected error without a context");
+        assert!(e.major.contains(MajorFlags::GSS_S_NO_CONTEXT));
+    }
+
+    #[cfg(all(feature = "krb5", gssapi_mit))]
+    #[test]
+    fn test_export_lucid() {
+        let (mut client, mut server) = setup();
+        establish(&mut client, &mut server);
+        let e = client
+            .export_lucid(2)
+            .expect_err("Expected error exporting an undefined version");
+        assert_eq!(e.major, MajorFlags::GSS_S_UNAVAILABLE);
+        let (mut client, mut server) = setup();
+        establish(&mut client, &mut server);
+        let client = client
+            .export_lucid(1)
+            .expect("Failed to export client context");
+        let server = server
+            .export_lucid(1)
+            .expect("Failed to export server context");
+        assert!(client.initiate);
+        assert!(!server.initiate);
+        assert_eq!(client.send_seq, server.recv_seq);
+        match (client.keys, server.keys) {
+            (
+                LucidKeys::Cfx {
+                    ctx_key: ckey,
+                    acceptor_subkey: csub,
+                },
+                LucidKeys::Cfx {
+                    ctx_key: skey,
+                    acceptor_subkey: ssub,
+                },
+            ) => {
+                assert_eq!(ckey.key(), skey.key());
+                assert_eq!(
+                    csub.map(|k| k.key().to_vec()),
+                    ssub.map(|k| k.key().to_vec())
+                );
+            }
+            (_, _) => panic!("Expected RFC 4121 keys"),
+        }
+    }
+
+    #[test]
+    fn test_supported_qops() {
+        let (mut client, mut server) = setup();
+        establish(&mut client, &mut server);
+        let qops = client.supported_qops().expect("Failed to get supported qops");
+        assert_eq!(qops[0], GSS_C_QOP_DEFAULT);
+    }
+
+    #[test]
+    fn test_supported_qops_no_context() {
+        let ctx = Ctx::from(ServerCtx::new(None));
+        let e = ctx
+            .supported_qops()
+            .expect_err("Expected error without a context");
+        assert!(e.major.contains(MajorFlags::GSS_S_NO_CONTEXT));
+    }
+
+    #[test]
+    fn test_server_needs_token() {
+        let mut server = Ctx::from(ServerCtx::new(None));
+        let e = server
+            .step(None)
+            .expect_err("Expected error stepping a server with no token");
+        assert_eq!(e.major, MajorFlags::GSS_S_DEFECTIVE_TOKEN);
+    }
+
+    #[test]
+    fn test_expected_peer_name_type() {
+        let (mut client, mut server) =
+            setup_with(|s| s.expected_peer_name_type(&GSS_NT_KRB5_PRINCIPAL));
+        establish(&mut client, &mut server);
+        let (mut client, mut server) =
+            setup_with(|s| s.expected_peer_name_type(&GSS_NT_EXPORT_NAME));
+        let tok = client.step(None).expect("Failed to step client").into_token();
+        let e = server
+            .step(tok.as_deref())
+            .expect_err("Expected peer name type mismatch");
+        assert!(e.major.contains(MajorFlags::GSS_S_BAD_NAMETYPE));
+    }
+
+    #[cfg(feature = "options")]
+    #[test]
+    fn test_set_option() {
+        use crate::oid::GSS_KRB5_CRED_NO_CI_FLAGS_X;
+        let mut server = Ctx::from(ServerCtx::new(None));
+        server
+            .set_option(&GSS_KRB5_CRED_NO_CI_FLAGS_X, b"")
+            .expect_err("Expected error setting an option on an empty context");
+    }
+
+    #[test]
+    fn test_client_builder() {
+        let e = ClientCtx::builder()
+            .flags(CtxFlags::GSS_C_MUTUAL_FLAG)
+            .build()
+            .expect_err("Expected error building a context without a target");
+        assert_eq!(e.major, MajorFlags::GSS_S_BAD_NAME);
+        let target = Name::new(b"host@example.com", Some(&GSS_NT_HOSTBASED_SERVICE))
+            .expect("Failed to import name");
+        let ctx = ClientCtx::builder()
+            .target(target)
+            .flags(CtxFlags::GSS_C_MUTUAL_FLAG)
+            .mech(&GSS_MECH_KRB5)
+            .lifetime(Duration::from_secs(60))
+            .channel_bindings(ChannelBindings::new(b"tls-server-end-point:"))
+            .build()
+            .expect("Failed to build client context");
+        assert!(!ctx.is_complete());
+        assert_eq!(ctx.time_req, Lifetime::Seconds(60));
+        let target = Name::new(b"host@example.com", Some(&GSS_NT_HOSTBASED_SERVICE))
+            .expect("Failed to import name");
+        let ctx = ClientCtx::builder()
+            .target(target)
+            .delegate_by_policy(true)
+            .flags(CtxFlags::GSS_C_MUTUAL_FLAG)
+            .build()
+            .expect("Failed to build client context");
+        assert_eq!(
+            ctx.requested_flags(),
+            CtxFlags::GSS_C_MUTUAL_FLAG | CtxFlags::GSS_C_DELEG_POLICY_FLAG
+        );
+        assert!(!ctx.delegated());
+        assert!(!ctx.delegation_suppressed());
+    }
+
+    #[test]
+    fn test_server_builder() {
+        let ctx = ServerCtx::builder()
+            .capture_delegated_cred(false)
+            .expected_peer_name_type(&GSS_NT_KRB5_PRINCIPAL)
+            .channel_bindings(ChannelBindings::new(b"tls-server-end-point:"))
+            .build();
+        assert!(!ctx.capture_delegated_cred);
+        assert!(!ctx.is_complete());
+        assert!(!ctx.channel_bound());
+        assert!(ctx.client_name().is_none());
+        assert!(ServerCtx::builder().build().capture_delegated_cred);
+        assert!(ServerCtx::new(None).capture_delegated_cred);
+    }
+
+    #[test]
+    fn test_step_empty_token() {
+        match Step::new(true, Buf::empty()) {
+            Step::Continue(None) => (),
+            step => panic!("Expected a continuation with no token, got {:?}", step),
+        }
+        match Step::new(false, Buf::empty()) {
+            Step::Done(None) => (),
+            step => panic!("Expected done with no token, got {:?}", step),
+        }
+        let step = Step::new(true, Buf::empty());
+        assert!(!step.is_done());
+        assert!(step.token().is_none());
+    }
+
+    #[test]
+    fn test_flags() {
+        let flags = CtxFlags::GSS_C_MUTUAL_FLAG | CtxFlags::GSS_C_CONF_FLAG;
+        assert_eq!(
+            format!("{:?}", flags),
+            "CtxFlags(GSS_C_MUTUAL_FLAG | GSS_C_CONF_FLAG)"
+        );
+        let target = Name::new(b"host@example.com", Some(&GSS_NT_HOSTBASED_SERVICE))
+            .expect("Failed to import name");
+        let ctx = ClientCtx::new(None, target, flags, Some(&GSS_MECH_KRB5));
+        assert_eq!(ctx.requested_flags(), flags);
+        assert_eq!(ctx.returned_flags(), CtxFlags::empty());
+    }
+
+    #[test]
+    fn test_require_flags() {
+        let (mut client, mut server) = setup();
+        establish(&mut client, &mut server);
+        // anonymity is never granted unless it was requested
+        let anon = CtxFlags::GSS_C_ANON_FLAG;
+        assert!(!client.returned_flags().contains(anon));
+        let e = require_flags(&client, anon).expect_err("Expected missing flag");
+        assert!(e.is_unauthorized());
+        require_flags(&client, CtxFlags::GSS_C_MUTUAL_FLAG).expect("Expected mutual auth");
+    }
+
+    #[test]
+    fn test_prot_ready() {
+        let (mut client, mut server) = setup();
+        assert!(!client.prot_ready());
+        let e = client
+            .wrap(true, b"too early")
+            .expect_err("Expected error wrapping before establishment");
+        assert!(e.major.contains(MajorFlags::GSS_S_NO_CONTEXT));
+        client.get_mic(b"too early").expect_err("Expected error getting a mic");
+        client
+            .require_mutual_auth()
+            .expect_err("Expected error requiring mutual auth before establishment");
+        establish(&mut client, &mut server);
+        client.require_mutual_auth().expect("Expected mutual auth");
+        client.require_integrity().expect("Expected integrity");
+        assert!(client.prot_ready());
+        assert!(server.prot_ready());
+        client.wrap(true, b"on time").expect("Failed to wrap message");
+    }
+
+    #[test]
+    fn test_process_token() {
+        let mut server = Ctx::from(ServerCtx::new(None));
+        server
+            .process_token(b"garbage")
+            .expect_err("Expected error processing a token with no context");
+    }
+
+    #[test]
+    fn test_delete() {
+        let server = Ctx::from(ServerCtx::new(None));
+        let tok = server.delete().expect("Failed to delete context");
+        assert!(tok.is_none());
+    }
+
+    #[test]
+    fn test_mech_before_establishment() {
+        let server = Ctx::from(ServerCtx::new(None));
+        server
+            .mech()
+            .expect_err("Expected error getting the mech of an empty context");
+    }
+
+    #[cfg(feature = "inquire")]
+    #[test]
+    fn test_inquire_by_oid() {
+        use crate::oid::GSS_INQ_SSPI_SESSION_KEY;
+        let server = Ctx::from(ServerCtx::new(None));
+        server
+            .inquire_by_oid(&GSS_INQ_SSPI_SESSION_KEY)
+            .expect_err("Expected error inquiring an empty context");
+    }
 }
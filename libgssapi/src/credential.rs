@@ -2,28 +2,39 @@ use crate::{
     error::{Error, MajorFlags, gss_error},
     name::Name,
     oid::{NO_OID, Oid},
-    oid::{NO_OID_SET, OidSet},
-    util::BufRef,
+    oid::{NO_OID_SET, OidSet, GSS_NT_HOSTBASED_SERVICE},
+    util::{BufRef, Lifetime},
 };
 #[cfg(feature = "s4u")]
 use crate::{
-    oid::{GSS_KRB5_GET_CRED_IMPERSONATOR, GSS_NT_HOSTBASED_SERVICE},
-    util::BufSet,
+    buffer_set::BufferSet,
+    oid::GSS_KRB5_GET_CRED_IMPERSONATOR,
 };
 use libgssapi_sys::{
-    _GSS_C_INDEFINITE, GSS_C_ACCEPT, GSS_C_BOTH, GSS_C_INITIATE, GSS_S_COMPLETE,
+    GSS_C_ACCEPT, GSS_C_BOTH, GSS_C_INITIATE, GSS_S_COMPLETE,
     OM_uint32, gss_OID_set, gss_acquire_cred, gss_acquire_cred_with_password,
     gss_cred_id_struct, gss_cred_id_t, gss_cred_usage_t, gss_inquire_cred,
     gss_name_struct, gss_name_t, gss_release_cred, gss_store_cred,
 };
 #[cfg(feature = "s4u")]
 use libgssapi_sys::{
-    gss_acquire_cred_impersonate_name, gss_inquire_cred_by_oid,
-    gss_key_value_element_desc, gss_key_value_set_desc, gss_store_cred_into,
+    gss_acquire_cred_impersonate_name, gss_add_cred_impersonate_name,
+    gss_inquire_cred_by_oid, gss_key_value_element_desc, gss_key_value_set_desc,
+    gss_store_cred_into,
 };
-#[cfg(feature = "s4u")]
+#[cfg(feature = "options")]
+use libgssapi_sys::gss_set_cred_option;
+#[cfg(all(feature = "krb5", gssapi_mit))]
+use libgssapi_sys::{gss_krb5_ccache_name, gss_krb5_set_allowable_enctypes};
+#[cfg(any(feature = "s4u", all(feature = "krb5", gssapi_mit)))]
 use std::ffi::{CStr, CString};
-use std::{ffi::c_int, fmt, ptr, sync::Arc, time::Duration};
+use std::{
+    collections::HashMap,
+    ffi::c_int,
+    fmt, ptr,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
 
 pub(crate) const NO_CRED: gss_cred_id_t = ptr::null_mut();
 
@@ -31,7 +42,7 @@ pub(crate) const NO_CRED: gss_cred_id_t = ptr::null_mut();
 pub struct CredInfo {
     pub name: Name,
     pub proxy: Option<Name>,
-    pub lifetime: Duration,
+    pub lifetime: Lifetime,
     pub usage: CredUsage,
     pub mechanisms: OidSet,
 }
@@ -99,10 +110,17 @@ impl Drop for CredInner {
     }
 }
 
+// The handle is only modified through `&mut CredInner`, which
+// set_option and set_allowable_enctypes only get while the `Cred` has
+// not been cloned. Once it is shared between threads the credential is
+// only read, so sharing it is sound.
 unsafe impl Send for CredInner {}
 unsafe impl Sync for CredInner {}
 
-/// gssapi credentials.
+/// gssapi credentials. Not every implementation has a way to duplicate
+/// a credential, so instead clones share it, it is released when the
+/// last clone is dropped. This makes it cheap to hand the same
+/// credential to many worker threads or tasks.
 #[derive(Clone)]
 pub struct Cred(Arc<CredInner>);
 
@@ -122,19 +140,16 @@ impl fmt::Debug for Cred {
 }
 
 impl Cred {
-    /// Acquire gssapi credentials for `name` or the default name,
-    /// lasting for `time_req` or as long as possible, for the purpose
-    /// of `usage`, and for use with `desired_mechs` or the default
-    /// mechanism.
+    /// Acquire gssapi credentials for `name`, or the default name if
+    /// `None`. The credentials will be valid for `time_req`, usable
+    /// for `usage`, and bound to `desired_mechs`, or the default
+    /// mechanism if `None`.
     pub fn acquire(
         name: Option<&Name>,
-        time_req: Option<Duration>,
+        time_req: Lifetime,
         usage: CredUsage,
         desired_mechs: Option<&OidSet>,
     ) -> Result<Cred, Error> {
-        let time_req = time_req
-            .map(|d| d.as_secs() as u32)
-            .unwrap_or(_GSS_C_INDEFINITE);
         let mut minor = GSS_S_COMPLETE;
         let usage = usage.to_c();
         let mut cred = ptr::null_mut::<gss_cred_id_struct>();
@@ -145,7 +160,7 @@ impl Cred {
                     None => ptr::null_mut::<gss_name_struct>(),
                     Some(n) => n.to_c(),
                 },
-                time_req,
+                time_req.to_c(),
                 match desired_mechs {
                     None => NO_OID_SET,
                     Some(desired_mechs) => desired_mechs.to_c(),
@@ -166,16 +181,28 @@ impl Cred {
         }
     }
 
+    /// Acquire acceptor credentials for `service`, a hostbased
+    /// service name like `host@example.com` (or just `host` for the
+    /// local host), with the default mechanisms and an indefinite
+    /// lifetime. If `service` is `None` or empty the credentials can
+    /// accept as any principal in the default keytab.
+    pub fn acquire_acceptor(service: Option<&str>) -> Result<Cred, Error> {
+        let name = match service {
+            None | Some("") => None,
+            Some(service) => {
+                Some(Name::new(service.as_bytes(), Some(&GSS_NT_HOSTBASED_SERVICE))?)
+            }
+        };
+        Cred::acquire(name.as_ref(), Lifetime::Indefinite, CredUsage::Accept, None)
+    }
+
     pub fn acquire_with_password(
         name: Option<&Name>,
         password: &str,
-        time_req: Option<Duration>,
+        time_req: Lifetime,
         usage: CredUsage,
         desired_mechs: Option<&OidSet>,
     ) -> Result<Cred, Error> {
-        let time_req = time_req
-            .map(|d| d.as_secs() as u32)
-            .unwrap_or(_GSS_C_INDEFINITE);
         let mut minor = GSS_S_COMPLETE;
         let usage = usage.to_c();
         let mut cred = ptr::null_mut::<gss_cred_id_struct>();
@@ -186,8 +213,8 @@ impl Cred {
                     None => ptr::null_mut::<gss_name_struct>(),
                     Some(n) => n.to_c(),
                 },
-                BufRef::from(password.as_bytes()).to_c(),
-                time_req,
+                BufRef::from(password).to_c(),
+                time_req.to_c(),
                 match desired_mechs {
                     None => NO_OID_SET,
                     Some(desired_mechs) => desired_mechs.to_c(),
@@ -208,17 +235,33 @@ impl Cred {
         }
     }
 
+    /// Acquire credentials for `name` without their involvement
+    /// (S4U2Self), using `impersonator`. The impersonator must be
+    /// permitted to perform protocol transition by the KDC. The
+    /// returned credential can initiate contexts to other services as
+    /// `name`, subject to constrained delegation policy. This is the
+    /// same as `impersonator.impersonate(..)`.
+    #[cfg(feature = "s4u")]
+    pub fn acquire_impersonate(
+        impersonator: &Cred,
+        name: &Name,
+        time_req: Lifetime,
+        desired_mechs: Option<&OidSet>,
+        usage: CredUsage,
+    ) -> Result<Cred, Error> {
+        impersonator.impersonate(name, time_req, usage, desired_mechs)
+    }
+
+    /// Acquire credentials for `name` using this credential as the
+    /// impersonator. See `acquire_impersonate`.
     #[cfg(feature = "s4u")]
     pub fn impersonate(
         &self,
         name: &Name,
-        time_req: Option<Duration>,
+        time_req: Lifetime,
         usage: CredUsage,
         desired_mechs: Option<&OidSet>,
     ) -> Result<Cred, Error> {
-        let time_req = time_req
-            .map(|d| d.as_secs() as u32)
-            .unwrap_or(_GSS_C_INDEFINITE);
         let mut minor = GSS_S_COMPLETE;
         let usage = usage.to_c();
         let mut cred = ptr::null_mut::<gss_cred_id_struct>();
@@ -227,7 +270,7 @@ impl Cred {
                 &mut minor as *mut OM_uint32,
                 self.to_c(),
                 name.to_c(),
-                time_req,
+                time_req.to_c(),
                 match desired_mechs {
                     None => NO_OID_SET,
                     Some(desired_mechs) => desired_mechs.to_c(),
@@ -248,6 +291,49 @@ impl Cred {
         }
     }
 
+    /// Add a credential element for `mech` impersonating `name` to a
+    /// copy of this credential, using `impersonator`. This extends an
+    /// impersonated credential (see `acquire_impersonate`) to
+    /// additional mechanisms.
+    #[cfg(feature = "s4u")]
+    pub fn add_impersonate(
+        &self,
+        impersonator: &Cred,
+        name: &Name,
+        mech: &Oid,
+        usage: CredUsage,
+        init_time: Lifetime,
+        accept_time: Lifetime,
+    ) -> Result<Cred, Error> {
+        let mut minor = GSS_S_COMPLETE;
+        let usage = usage.to_c();
+        let mut cred = ptr::null_mut::<gss_cred_id_struct>();
+        let major = unsafe {
+            gss_add_cred_impersonate_name(
+                &mut minor as *mut OM_uint32,
+                self.to_c(),
+                impersonator.to_c(),
+                name.to_c(),
+                mech.to_c(),
+                usage as gss_cred_usage_t,
+                init_time.to_c(),
+                accept_time.to_c(),
+                &mut cred as *mut gss_cred_id_t,
+                ptr::null_mut::<gss_OID_set>(),
+                ptr::null_mut::<OM_uint32>(),
+                ptr::null_mut::<OM_uint32>(),
+            )
+        };
+        if major == GSS_S_COMPLETE {
+            Ok(Cred::from(cred))
+        } else {
+            Err(Error {
+                major: MajorFlags::from_bits_retain(major),
+                minor,
+            })
+        }
+    }
+
     /// Store the credential into the specified credential cache
     #[cfg(feature = "s4u")]
     pub fn store_into(
@@ -334,6 +420,76 @@ impl Cred {
         }
     }
 
+    /// Set the mechanism specific option `desired` on the credential
+    /// to `value`. The supported options, and the encoding of their
+    /// values, depend on the mechanism and the implementation. For
+    /// example with krb5 `GSS_KRB5_CRED_NO_CI_FLAGS_X` and an empty
+    /// value stops the initiator from asserting the confidentiality
+    /// and integrity flags, which some acceptors reject. Options must
+    /// be set before the `Cred` is cloned, this fails with
+    /// `GSS_S_FAILURE` if it has been.
+    #[cfg(feature = "options")]
+    pub fn set_option(&mut self, desired: &Oid, value: &[u8]) -> Result<(), Error> {
+        let mut minor = GSS_S_COMPLETE;
+        let mut value = BufRef::from(value);
+        let inner = self.unshared()?;
+        // the implementation may allocate a credential if we didn't have one
+        let major = unsafe {
+            gss_set_cred_option(
+                &mut minor as *mut OM_uint32,
+                &mut inner.0 as *mut gss_cred_id_t,
+                desired.to_c(),
+                value.to_c(),
+            )
+        };
+        if major == GSS_S_COMPLETE {
+            Ok(())
+        } else {
+            Err(Error {
+                major: MajorFlags::from_bits_retain(major),
+                minor,
+            })
+        }
+    }
+
+    /// Restrict the krb5 encryption types this credential may use to
+    /// `enctypes`, e.g. `&[18, 17]` for aes256-cts-hmac-sha1-96 and
+    /// aes128-cts-hmac-sha1-96 only. Like `set_option` this fails with
+    /// `GSS_S_FAILURE` if the `Cred` has been cloned. Only available
+    /// with MIT Kerberos.
+    #[cfg(all(feature = "krb5", gssapi_mit))]
+    pub fn set_allowable_enctypes(&mut self, enctypes: &[i32]) -> Result<(), Error> {
+        let mut minor = GSS_S_COMPLETE;
+        let inner = self.unshared()?;
+        // the C signature isn't const, but it doesn't modify the array
+        let mut enctypes = enctypes.to_vec();
+        let major = unsafe {
+            gss_krb5_set_allowable_enctypes(
+                &mut minor as *mut OM_uint32,
+                inner.0,
+                enctypes.len() as OM_uint32,
+                enctypes.as_mut_ptr(),
+            )
+        };
+        if major == GSS_S_COMPLETE {
+            Ok(())
+        } else {
+            Err(Error {
+                major: MajorFlags::from_bits_retain(major),
+                minor,
+            })
+        }
+    }
+
+    // mutable access to the credential, only while no clone can see it
+    #[cfg(any(feature = "options", all(feature = "krb5", gssapi_mit)))]
+    fn unshared(&mut self) -> Result<&mut CredInner, Error> {
+        Arc::get_mut(&mut self.0).ok_or(Error {
+            major: MajorFlags::GSS_S_FAILURE,
+            minor: 0,
+        })
+    }
+
     pub(crate) unsafe fn from_c(cred: gss_cred_id_t) -> Cred {
         Cred::from(cred)
     }
@@ -393,7 +549,7 @@ impl Cred {
             Ok(CredInfo {
                 name: Name::from_c(c.name.unwrap()),
                 proxy: self.proxy()?,
-                lifetime: Duration::from_secs(c.lifetime.unwrap() as u64),
+                lifetime: Lifetime::from_c(c.lifetime.unwrap()),
                 usage: CredUsage::from_c(c.usage.unwrap())?,
                 mechanisms: OidSet::from_c(c.mechanisms.unwrap()),
             })
@@ -415,7 +571,7 @@ impl Cred {
     pub fn proxy(&self) -> Result<Option<Name>, Error> {
         #[cfg(feature = "s4u")]
         unsafe {
-            let mut out = BufSet::empty();
+            let mut out = BufferSet::empty();
             let mut minor: u32 = 0;
             let major = gss_inquire_cred_by_oid(
                 &mut minor as *mut OM_uint32,
@@ -441,13 +597,27 @@ impl Cred {
     }
 
     /// Return the lifetime of this credential
-    pub fn lifetime(&self) -> Result<Duration, Error> {
+    pub fn lifetime(&self) -> Result<Lifetime, Error> {
         unsafe {
             let c = self.info_c(CredInfoC {
                 lifetime: Some(0),
                 ..CredInfoC::empty()
             })?;
-            Ok(Duration::from_secs(c.lifetime.unwrap() as u64))
+            Ok(Lifetime::from_c(c.lifetime.unwrap()))
+        }
+    }
+
+    /// Return how much longer this credential is valid for, failing
+    /// with `GSS_S_CREDENTIALS_EXPIRED` once it has expired. Unlike
+    /// `lifetime` an expired credential is always an error, so a
+    /// connection manager can schedule renewal on `Ok`.
+    pub fn time_remaining(&self) -> Result<Lifetime, Error> {
+        match self.lifetime()? {
+            Lifetime::Seconds(0) => Err(Error {
+                major: MajorFlags::GSS_S_CREDENTIALS_EXPIRED,
+                minor: 0,
+            }),
+            lifetime => Ok(lifetime),
         }
     }
 
@@ -474,13 +644,119 @@ impl Cred {
     }
 }
 
+/// A cache of acceptor credentials keyed by service name, for servers
+/// that would otherwise acquire the same credential on every
+/// accept. A cached credential is re-acquired once it has less than
+/// `min_remaining` left, so accepts never fail because of a stale
+/// credential. It can be shared between threads.
+#[derive(Debug)]
+pub struct CredCache {
+    min_remaining: Duration,
+    creds: Mutex<HashMap<String, Arc<Mutex<Option<Cred>>>>>,
+}
+
+impl CredCache {
+    /// Create an empty cache that re-acquires credentials with less
+    /// than `min_remaining` left.
+    pub fn new(min_remaining: Duration) -> CredCache {
+        CredCache {
+            min_remaining,
+            creds: Mutex::new(HashMap::new()),
+        }
+    }
+
+    fn fresh(&self, cred: &Cred) -> bool {
+        match cred.time_remaining() {
+            Ok(Lifetime::Indefinite) => true,
+            Ok(Lifetime::Seconds(secs)) => {
+                Duration::from_secs(secs as u64) >= self.min_remaining
+            }
+            Err(_) => false,
+        }
+    }
+
+    /// Return the acceptor credential for `service` (see
+    /// `Cred::acquire_acceptor`), acquiring it if it isn't cached or
+    /// is about to expire. The returned `Cred` shares the cached
+    /// credential. Concurrent callers for the same service wait for a
+    /// single acquisition, other services aren't held up by it.
+    pub fn get(&self, service: &str) -> Result<Cred, Error> {
+        // the map is only locked long enough to find the entry, the
+        // entry is locked while acquiring
+        let entry = self
+            .creds
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .entry(service.to_string())
+            .or_default()
+            .clone();
+        let mut cred = entry.lock().unwrap_or_else(|e| e.into_inner());
+        match &*cred {
+            Some(cred) if self.fresh(cred) => Ok(cred.clone()),
+            Some(_) | None => {
+                let acquired = Cred::acquire_acceptor(Some(service))?;
+                *cred = Some(acquired.clone());
+                Ok(acquired)
+            }
+        }
+    }
+
+    /// Drop the cached credential for `service`, if any
+    pub fn remove(&self, service: &str) {
+        self.creds
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .remove(service);
+    }
+}
+
+/// Point the krb5 mechanism at the credential cache `name` (e.g.
+/// `FILE:/tmp/krb5cc_request`) for subsequent operations, returning
+/// the name of the previously used cache, if any.
+///
+/// This mutates global state in the krb5 library. With MIT Kerberos
+/// the setting is per thread, so it can be used to isolate the
+/// credentials of concurrent requests as long as each request stays on
+/// one thread, but it will leak between requests that share a thread
+/// (e.g. tasks on an async executor) unless it is restored. It also
+/// affects every credential acquired or stored (see `Cred::store`)
+/// without an explicit ccache afterwards. Prefer `Cred::store_into`
+/// when a specific cache is all you need. Only available with MIT
+/// Kerberos.
+#[cfg(all(feature = "krb5", gssapi_mit))]
+pub fn krb5_set_ccache_name(name: &str) -> Result<Option<String>, Error> {
+    let name = CString::new(name).map_err(|_| Error {
+        major: MajorFlags::GSS_S_BAD_NAME,
+        minor: 0,
+    })?;
+    let mut minor = GSS_S_COMPLETE;
+    let mut old = ptr::null();
+    let major = unsafe {
+        gss_krb5_ccache_name(&mut minor as *mut OM_uint32, name.as_ptr(), &mut old)
+    };
+    if major == GSS_S_COMPLETE {
+        if old.is_null() {
+            Ok(None)
+        } else {
+            // old is owned by the library and only valid until the next call
+            let old = unsafe { CStr::from_ptr(old) };
+            Ok(Some(old.to_string_lossy().into_owned()))
+        }
+    } else {
+        Err(Error {
+            major: MajorFlags::from_bits_retain(major),
+            minor,
+        })
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
 
     #[test]
     fn test_acquire() {
-        Cred::acquire(None, None, CredUsage::Both, None)
+        Cred::acquire(None, Lifetime::Indefinite, CredUsage::Both, None)
             .expect("Failed to acquire credential");
     }
 
@@ -490,4 +766,93 @@ mod tests {
         c.store_default(true, true, CredUsage::Both, None)
             .expect_err("Expected error when storing empty credential");
     }
+
+    #[test]
+    fn test_acquire_acceptor() {
+        Cred::acquire_acceptor(Some("host"))
+            .expect("Failed to acquire acceptor credential");
+        Cred::acquire_acceptor(None).expect("Failed to acquire acceptor credential");
+    }
+
+    #[test]
+    fn test_clone_shares() {
+        fn shareable<T: Send + Sync + Clone>() {}
+        shareable::<Cred>();
+        let c = Cred::acquire(None, Lifetime::Indefinite, CredUsage::Both, None)
+            .expect("Failed to acquire credential");
+        let d = c.clone();
+        assert_eq!(unsafe { c.to_c() }, unsafe { d.to_c() });
+        drop(c);
+        d.info().expect("Failed to get info of a clone");
+    }
+
+    #[test]
+    fn test_cred_cache() {
+        let cache = CredCache::new(Duration::from_secs(60));
+        let a = cache.get("host").expect("Failed to get credential");
+        let b = cache.get("host").expect("Failed to get credential");
+        assert_eq!(unsafe { a.to_c() }, unsafe { b.to_c() });
+        cache.remove("host");
+        let c = cache.get("host").expect("Failed to get credential");
+        assert_ne!(unsafe { a.to_c() }, unsafe { c.to_c() });
+    }
+
+    #[cfg(feature = "s4u")]
+    #[test]
+    fn test_add_impersonate() {
+        use crate::oid::{GSS_MECH_KRB5, GSS_NT_USER_NAME};
+        let name = Name::new(b"nobody@EXAMPLE.COM", Some(&GSS_NT_USER_NAME))
+            .expect("Failed to import name");
+        let c = unsafe { Cred::from_c(NO_CRED) };
+        let impersonator = unsafe { Cred::from_c(NO_CRED) };
+        let e = c
+            .add_impersonate(
+                &impersonator,
+                &name,
+                &GSS_MECH_KRB5,
+                CredUsage::Initiate,
+                Lifetime::Indefinite,
+                Lifetime::Indefinite,
+            )
+            .expect_err("Expected error impersonating with an empty credential");
+        assert!(gss_error(e.major.bits()) > 0);
+    }
+
+    #[cfg(feature = "options")]
+    #[test]
+    fn test_set_option() {
+        use crate::oid::GSS_MECH_KRB5;
+        let mut c = unsafe { Cred::from_c(NO_CRED) };
+        c.set_option(&GSS_MECH_KRB5, b"")
+            .expect_err("Expected error setting an unknown option");
+        let _shared = c.clone();
+        let e = c
+            .set_option(&GSS_MECH_KRB5, b"")
+            .expect_err("Expected error setting an option on a shared credential");
+        assert_eq!(e.major, MajorFlags::GSS_S_FAILURE);
+    }
+
+    #[cfg(all(feature = "krb5", gssapi_mit))]
+    #[test]
+    fn test_set_allowable_enctypes() {
+        let mut c = Cred::acquire(None, Lifetime::Indefinite, CredUsage::Initiate, None)
+            .expect("Failed to acquire credential");
+        c.set_allowable_enctypes(&[18, 17])
+            .expect("Failed to set allowable enctypes");
+        let _shared = c.clone();
+        c.set_allowable_enctypes(&[18])
+            .expect_err("Expected error restricting a shared credential");
+    }
+
+    #[cfg(all(feature = "krb5", gssapi_mit))]
+    #[test]
+    fn test_krb5_set_ccache_name() {
+        krb5_set_ccache_name("MEMORY:first").expect("Failed to set ccache name");
+        let old =
+            krb5_set_ccache_name("MEMORY:second").expect("Failed to set ccache name");
+        assert_eq!(old.as_deref(), Some("MEMORY:first"));
+        let e = krb5_set_ccache_name("bad\0name")
+            .expect_err("Expected error with a nul in the name");
+        assert_eq!(e.major, MajorFlags::GSS_S_BAD_NAME);
+    }
 }
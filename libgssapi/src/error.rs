@@ -15,7 +15,7 @@ use libgssapi_sys::{
 use std::{error, fmt, ptr, os::raw::c_int};
 
 bitflags! {
-    #[derive(Clone, Copy, Debug)]
+    #[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
     pub struct MajorFlags: u32 {
         // calling errors
         const GSS_S_CALL_INACCESSIBLE_READ = _GSS_S_CALL_INACCESSIBLE_READ;
@@ -70,7 +70,70 @@ pub struct Error {
     pub minor: u32,
 }
 
+/// Why a per message token (e.g. a mic) was rejected, see
+/// `Error::token_status`. Everything but `BadSig` is a replay or
+/// sequencing problem rather than an integrity failure, and is only
+/// reported if replay or sequence detection was requested for the
+/// context.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum TokenStatus {
+    /// The integrity check failed, the token was forged or corrupted
+    BadSig,
+    /// The token was already processed (it was replayed)
+    Duplicate,
+    /// The token is too old to check whether it's a duplicate
+    Old,
+    /// A later token has already been processed
+    Unseq,
+    /// An earlier token was skipped
+    Gap,
+}
+
 impl Error {
+    fn routine(&self) -> u32 {
+        self.major.bits() & (_GSS_C_ROUTINE_ERROR_MASK << GSS_C_ROUTINE_ERROR_OFFSET)
+    }
+
+    /// True if this error means the implementation or mechanism
+    /// doesn't support the operation (`GSS_S_UNAVAILABLE` or
+    /// `GSS_S_BAD_MECH`), rather than that the operation failed. The
+    /// wrappers for extensions are the usual source, e.g.
+    /// `wrap_iov` and friends, `export_lucid`, `inquire_by_oid`,
+    /// `set_option` on contexts and credentials, `Name::display_as`,
+    /// `Name::is_mechanism_name`, `oid::mech_attrs`, and
+    /// `oid::sasl_name_for_mech`. Callers can use it to fall back,
+    /// e.g. to plain `wrap` instead of `wrap_iov`.
+    pub fn is_unsupported(&self) -> bool {
+        let routine = self.routine();
+        routine == _GSS_S_UNAVAILABLE || routine == _GSS_S_BAD_MECH
+    }
+
+    /// True if this is a `GSS_S_UNAUTHORIZED` error, e.g. because a
+    /// context didn't provide a service that was required of it (see
+    /// `SecurityContext::require_mutual_auth`).
+    pub fn is_unauthorized(&self) -> bool {
+        self.routine() == _GSS_S_UNAUTHORIZED
+    }
+
+    /// Classify an error from verifying a per message token, or
+    /// `None` if it isn't one of the failures `TokenStatus`
+    /// describes.
+    pub fn token_status(&self) -> Option<TokenStatus> {
+        if self.routine() == _GSS_S_BAD_SIG {
+            Some(TokenStatus::BadSig)
+        } else if self.major.contains(MajorFlags::GSS_S_DUPLICATE_TOKEN) {
+            Some(TokenStatus::Duplicate)
+        } else if self.major.contains(MajorFlags::GSS_S_OLD_TOKEN) {
+            Some(TokenStatus::Old)
+        } else if self.major.contains(MajorFlags::GSS_S_UNSEQ_TOKEN) {
+            Some(TokenStatus::Unseq)
+        } else if self.major.contains(MajorFlags::GSS_S_GAP_TOKEN) {
+            Some(TokenStatus::Gap)
+        } else {
+            None
+        }
+    }
+
     fn fmt_code(f: &mut fmt::Formatter<'_>, code: u32, ctype: ErrorComponent) -> fmt::Result {
         let mut message_context: OM_uint32 = 0;
         loop {
@@ -113,3 +176,46 @@ impl fmt::Display for Error {
 }
 
 impl error::Error for Error {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_token_status() {
+        let status = |major| Error { major, minor: 0 }.token_status();
+        assert_eq!(status(MajorFlags::GSS_S_BAD_SIG), Some(TokenStatus::BadSig));
+        assert_eq!(
+            status(MajorFlags::GSS_S_DUPLICATE_TOKEN),
+            Some(TokenStatus::Duplicate)
+        );
+        assert_eq!(status(MajorFlags::GSS_S_OLD_TOKEN), Some(TokenStatus::Old));
+        assert_eq!(status(MajorFlags::GSS_S_UNSEQ_TOKEN), Some(TokenStatus::Unseq));
+        assert_eq!(
+            status(MajorFlags::GSS_S_GAP_TOKEN | MajorFlags::GSS_S_UNSEQ_TOKEN),
+            Some(TokenStatus::Unseq)
+        );
+        assert_eq!(status(MajorFlags::GSS_S_DEFECTIVE_TOKEN), None);
+        assert_eq!(status(MajorFlags::GSS_S_NO_CONTEXT), None);
+    }
+
+    #[test]
+    fn test_is_unsupported() {
+        let error = |major| Error { major, minor: 0 };
+        assert!(error(MajorFlags::GSS_S_UNAVAILABLE).is_unsupported());
+        assert!(error(MajorFlags::GSS_S_BAD_MECH).is_unsupported());
+        let read = MajorFlags::GSS_S_CALL_INACCESSIBLE_READ;
+        assert!(error(read | MajorFlags::GSS_S_UNAVAILABLE).is_unsupported());
+        assert!(!error(MajorFlags::GSS_S_FAILURE).is_unsupported());
+        assert!(!error(MajorFlags::GSS_S_BAD_MECH_ATTR).is_unsupported());
+        assert!(!error(MajorFlags::GSS_S_UNAUTHORIZED).is_unsupported());
+    }
+
+    #[test]
+    fn test_is_unauthorized() {
+        let error = |major| Error { major, minor: 0 };
+        assert!(error(MajorFlags::GSS_S_UNAUTHORIZED).is_unauthorized());
+        assert!(!error(MajorFlags::GSS_S_FAILURE).is_unauthorized());
+        assert!(!error(MajorFlags::GSS_S_UNAVAILABLE).is_unauthorized());
+    }
+}
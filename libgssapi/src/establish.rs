@@ -0,0 +1,187 @@
+//! Drive context establishment over an async byte stream.
+//!
+//! Tokens are framed with a 4 byte big endian length prefix, the same
+//! as [`framing`](../framing/index.html). Both sides of the connection
+//! must use this helper (or the same framing) for the exchange to work.
+use crate::{
+    context::Ctx,
+    error,
+    framing,
+};
+use std::{fmt, io};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+
+pub use crate::framing::MAX_TOKEN_LEN;
+
+#[derive(Debug)]
+pub enum Error {
+    /// The mechanism rejected a token
+    Gss(error::Error),
+    /// Reading or writing the transport failed, or the peer sent a
+    /// badly framed token
+    Io(io::Error),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Gss(e) => fmt::Display::fmt(e, f),
+            Error::Io(e) => fmt::Display::fmt(e, f),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Gss(e) => Some(e),
+            Error::Io(e) => Some(e),
+        }
+    }
+}
+
+impl From<error::Error> for Error {
+    fn from(e: error::Error) -> Self {
+        Error::Gss(e)
+    }
+}
+
+impl From<io::Error> for Error {
+    fn from(e: io::Error) -> Self {
+        Error::Io(e)
+    }
+}
+
+async fn read_token<R: AsyncRead + Unpin>(reader: &mut R) -> Result<Vec<u8>, Error> {
+    let mut len = [0u8; 4];
+    reader.read_exact(&mut len).await?;
+    let mut tok = vec![0u8; framing::decode_len(len, MAX_TOKEN_LEN)?];
+    reader.read_exact(&mut tok).await?;
+    Ok(tok)
+}
+
+async fn write_token<W: AsyncWrite + Unpin>(
+    writer: &mut W,
+    tok: &[u8],
+) -> Result<(), Error> {
+    writer.write_all(&framing::encode_len(tok.len())?).await?;
+    writer.write_all(tok).await?;
+    Ok(writer.flush().await?)
+}
+
+/// Run the token exchange for `ctx` to completion, reading the
+/// peer's tokens from `reader` and writing ours to `writer`. Returns
+/// once the context is open on this side, after any final token has
+/// been sent to the peer.
+///
+/// The initiator always speaks first, so a `Ctx::Client` starts by
+/// stepping with no token, while a `Ctx::Server` starts by reading
+/// one. A step that doesn't produce an output token just waits for
+/// the peer's next token.
+pub async fn establish<R, W>(
+    ctx: &mut Ctx,
+    reader: &mut R,
+    writer: &mut W,
+) -> Result<(), Error>
+where
+    R: AsyncRead + Unpin,
+    W: AsyncWrite + Unpin,
+{
+    let mut tok = match ctx {
+        Ctx::Client(_) => None,
+        Ctx::Server(_) => Some(read_token(reader).await?),
+    };
+    loop {
+        let step = ctx.step(tok.as_deref())?;
+        if let Some(out) = step.token() {
+            write_token(writer, out).await?;
+        }
+        if step.is_done() {
+            break Ok(());
+        }
+        tok = Some(read_token(reader).await?);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        context::{ClientCtx, CtxFlags, SecurityContext, ServerCtx},
+        name::Name,
+        oid::{GSS_MECH_KRB5, GSS_NT_HOSTBASED_SERVICE},
+    };
+    use std::future::Future;
+    use tokio::io::{duplex, split};
+
+    fn block_on<F: Future>(f: F) -> F::Output {
+        tokio::runtime::Builder::new_current_thread()
+            .build()
+            .expect("Failed to build runtime")
+            .block_on(f)
+    }
+
+    #[test]
+    fn test_framing() {
+        block_on(async {
+            let (mut a, mut b) = duplex(64);
+            write_token(&mut a, b"hello").await.expect("Failed to write token");
+            write_token(&mut a, b"").await.expect("Failed to write token");
+            assert_eq!(read_token(&mut b).await.expect("Failed to read token"), b"hello");
+            assert_eq!(read_token(&mut b).await.expect("Failed to read token"), b"");
+            drop(a);
+            match read_token(&mut b).await {
+                Err(Error::Io(e)) => assert_eq!(e.kind(), io::ErrorKind::UnexpectedEof),
+                r => panic!("Expected eof, got {:?}", r),
+            }
+        })
+    }
+
+    #[test]
+    fn test_too_large() {
+        block_on(async {
+            let (mut a, b) = duplex(64);
+            let len = (MAX_TOKEN_LEN as u32 + 1).to_be_bytes();
+            a.write_all(&len).await.expect("Failed to write length");
+            let mut server = Ctx::from(ServerCtx::new(None));
+            let (mut r, mut w) = split(b);
+            match establish(&mut server, &mut r, &mut w).await {
+                Err(Error::Io(e)) => assert_eq!(e.kind(), io::ErrorKind::InvalidData),
+                r => panic!("Expected an oversize token error, got {:?}", r),
+            }
+            assert!(!server.is_complete());
+        })
+    }
+
+    #[test]
+    fn test_establish() {
+        let target = Name::new(b"host", Some(&GSS_NT_HOSTBASED_SERVICE))
+            .expect("Failed to import service name")
+            .canonicalize(Some(&GSS_MECH_KRB5))
+            .expect("Failed to canonicalize service name");
+        let flags = CtxFlags::GSS_C_MUTUAL_FLAG;
+        let mut client = Ctx::from(ClientCtx::new(None, target, flags, Some(&GSS_MECH_KRB5)));
+        let mut server = Ctx::from(ServerCtx::new(None));
+        let (a, b) = duplex(64 * 1024);
+        let (mut cr, mut cw) = split(a);
+        let (mut sr, mut sw) = split(b);
+        let mut server = block_on(async {
+            // the server runs as a task while the client runs here
+            let server = tokio::spawn(async move {
+                establish(&mut server, &mut sr, &mut sw)
+                    .await
+                    .expect("Failed to establish server context");
+                server
+            });
+            establish(&mut client, &mut cr, &mut cw)
+                .await
+                .expect("Failed to establish client context");
+            server.await.expect("Server task failed")
+        });
+        assert!(client.is_complete());
+        assert!(server.is_complete());
+        let msg = client.wrap(true, b"hello").expect("Failed to wrap message");
+        let msg = server.unwrap(&msg).expect("Failed to unwrap message");
+        assert_eq!(&*msg, b"hello");
+    }
+}
@@ -0,0 +1,103 @@
+//! Length prefixed token framing for blocking streams.
+//!
+//! Each token is sent as a 4 byte big endian length followed by the
+//! token itself, the same framing used by the `tokio` establish
+//! helper and by many KRB5 based protocols. Zero length tokens are
+//! allowed and round trip as an empty `Vec`.
+use std::io::{self, Read, Write};
+
+/// The largest token `read_token` will accept from the peer. Kerberos
+/// tokens carrying a PAC can be large, but never anywhere near this.
+pub const MAX_TOKEN_LEN: usize = 1024 * 1024;
+
+/// The length prefix for a token of `len` bytes, failing with
+/// `InvalidInput` if it is too long to be framed
+pub(crate) fn encode_len(len: usize) -> io::Result<[u8; 4]> {
+    if len > u32::MAX as usize {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("token too large ({} bytes)", len),
+        ));
+    }
+    Ok((len as u32).to_be_bytes())
+}
+
+/// The length of the token following the prefix `len`, failing with
+/// `InvalidData` if it is longer than `max`
+pub(crate) fn decode_len(len: [u8; 4], max: usize) -> io::Result<usize> {
+    let len = u32::from_be_bytes(len) as usize;
+    if len > max {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("token too large ({} bytes)", len),
+        ));
+    }
+    Ok(len)
+}
+
+/// Write `tok` to `w` with its length prefix. Fails with
+/// `InvalidInput` if `tok` is too long to be framed.
+pub fn write_token<W: Write>(mut w: W, tok: &[u8]) -> io::Result<()> {
+    w.write_all(&encode_len(tok.len())?)?;
+    w.write_all(tok)?;
+    w.flush()
+}
+
+/// Read one token from `r`, refusing any longer than
+/// `MAX_TOKEN_LEN`.
+pub fn read_token<R: Read>(r: R) -> io::Result<Vec<u8>> {
+    read_token_max(r, MAX_TOKEN_LEN)
+}
+
+/// Read one token from `r`, refusing any longer than `max`. The
+/// length is checked before anything is allocated, so a hostile peer
+/// can't make us allocate more than `max` bytes.
+pub fn read_token_max<R: Read>(mut r: R, max: usize) -> io::Result<Vec<u8>> {
+    let mut len = [0u8; 4];
+    r.read_exact(&mut len)?;
+    let mut tok = vec![0u8; decode_len(len, max)?];
+    r.read_exact(&mut tok)?;
+    Ok(tok)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    #[test]
+    fn test_round_trip() {
+        let mut wire = Vec::new();
+        write_token(&mut wire, b"hello").expect("Failed to write token");
+        write_token(&mut wire, b"").expect("Failed to write token");
+        write_token(&mut wire, b"world").expect("Failed to write token");
+        assert_eq!(&wire[..9], b"\0\0\0\x05hello");
+        let mut r = Cursor::new(wire);
+        assert_eq!(read_token(&mut r).expect("Failed to read token"), b"hello");
+        assert_eq!(read_token(&mut r).expect("Failed to read token"), b"");
+        assert_eq!(read_token(&mut r).expect("Failed to read token"), b"world");
+        let e = read_token(&mut r).expect_err("Read past the end");
+        assert_eq!(e.kind(), io::ErrorKind::UnexpectedEof);
+    }
+
+    #[test]
+    fn test_too_large() {
+        let wire = u32::MAX.to_be_bytes();
+        let e = read_token(&wire[..]).expect_err("Accepted a huge token");
+        assert_eq!(e.kind(), io::ErrorKind::InvalidData);
+        let mut wire = Vec::new();
+        write_token(&mut wire, b"hello").expect("Failed to write token");
+        let e = read_token_max(&wire[..], 4).expect_err("Accepted a long token");
+        assert_eq!(e.kind(), io::ErrorKind::InvalidData);
+        assert_eq!(
+            read_token_max(&wire[..], 5).expect("Failed to read token"),
+            b"hello"
+        );
+    }
+
+    #[test]
+    fn test_truncated() {
+        let e = read_token(&b"\0\0\0\x05hel"[..]).expect_err("Accepted a short token");
+        assert_eq!(e.kind(), io::ErrorKind::UnexpectedEof);
+    }
+}
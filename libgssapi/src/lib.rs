@@ -14,14 +14,21 @@
 //! messages that only the other side can read. Other mechanisms may
 //! or may not provide this feature.
 //!
-//! * Initiate a new connection with a [`ClientCtx`](context/struct.ClientCtx.html)
-//! * Accept a client connection with a [`ServerCtx`](context/struct.ServerCtx.html)
+//! * Initiate a new connection with a [`ClientCtx`](context/struct.ClientCtx.html),
+//!   usually built with a [`ClientCtxBuilder`](context/struct.ClientCtxBuilder.html)
+//! * Accept a client connection with a [`ServerCtx`](context/struct.ServerCtx.html),
+//!   usually built with a [`ServerCtxBuilder`](context/struct.ServerCtxBuilder.html)
 //! * Both types implement [`SecurityContext`](context/trait.SecurityContext.html)
+//! * Either can be wrapped in a [`Ctx`](context/enum.Ctx.html) when the
+//!   code using it shouldn't care which side it is
 //!
 //! Unlike SSL Gssapi is completely independent of the transport. It
 //! will give you tokens to send to the other side, and tell you when
 //! the context is established, it's up to you to decide how the data
-//! gets there.
+//! gets there. The [`framing`](framing/index.html) module has helpers
+//! for the common length prefixed wire format, and with the `tokio`
+//! feature enabled, [`establish`](establish/fn.establish.html) runs the
+//! token exchange over an async stream for you.
 //! 
 //! ```
 //! use std::env::args;
@@ -29,8 +36,8 @@
 //!     name::Name,
 //!     credential::{Cred, CredUsage},
 //!     error::Error,
-//!     context::{CtxFlags, ClientCtx, ServerCtx, SecurityContext},
-//!     util::Buf,
+//!     context::{CtxFlags, ClientCtx, ServerCtx, SecurityContext, Step},
+//!     util::{Buf, Lifetime},
 //!     oid::{OidSet, GSS_NT_HOSTBASED_SERVICE, GSS_MECH_KRB5},
 //! };
 //! 
@@ -41,9 +48,9 @@
 //!     let name = Name::new(service_name, Some(&GSS_NT_HOSTBASED_SERVICE))?;
 //!     let cname = name.canonicalize(Some(&GSS_MECH_KRB5))?;
 //!     let server_cred = Cred::acquire(
-//!         Some(&cname), None, CredUsage::Accept, Some(desired_mechs)
+//!         Some(&cname), Lifetime::Indefinite, CredUsage::Accept, Some(desired_mechs)
 //!     )?;
-//!     Ok((ServerCtx::new(server_cred), cname))
+//!     Ok((ServerCtx::new(Some(&server_cred)), cname))
 //! }
 //! 
 //! fn setup_client_ctx(
@@ -51,10 +58,10 @@
 //!     desired_mechs: &OidSet
 //! ) -> Result<ClientCtx, Error> {
 //!     let client_cred = Cred::acquire(
-//!         None, None, CredUsage::Initiate, Some(&desired_mechs)
+//!         None, Lifetime::Indefinite, CredUsage::Initiate, Some(&desired_mechs)
 //!     )?;
 //!     Ok(ClientCtx::new(
-//!         Some(client_cred), service_name, CtxFlags::GSS_C_MUTUAL_FLAG, Some(&GSS_MECH_KRB5)
+//!         Some(&client_cred), service_name, CtxFlags::GSS_C_MUTUAL_FLAG, Some(&GSS_MECH_KRB5)
 //!     ))
 //! }
 //! 
@@ -68,11 +75,11 @@
 //!     let mut client_ctx = setup_client_ctx(cname, &desired_mechs)?;
 //!     let mut server_tok: Option<Buf> = None;
 //!     loop {
-//!         match client_ctx.step(server_tok.as_ref().map(|b| &**b), None)? {
+//!         match client_ctx.step(server_tok.as_deref(), None)?.into_token() {
 //!             None => break,
 //!             Some(client_tok) => match server_ctx.step(&*client_tok)? {
-//!                 None => break,
-//!                 Some(tok) => { server_tok = Some(tok); }
+//!                 Step::Done(None) => break,
+//!                 Step::Continue(tok) | Step::Done(tok) => { server_tok = tok; }
 //!             }
 //!         }
 //!     }
@@ -88,7 +95,11 @@
 pub mod oid;
 pub mod error;
 pub mod util;
+pub mod buffer_set;
 pub mod name;
 pub mod credential;
 pub mod context;
+pub mod framing;
+#[cfg(feature = "tokio")]
+pub mod establish;
  
@@ -1,18 +1,28 @@
 use crate::{
     error::{Error, MajorFlags},
     util::{Buf, BufRef},
-    oid::Oid,
+    oid::{Oid, OidSet, GSS_NT_ANONYMOUS, GSS_NT_EXPORT_NAME},
 };
 use libgssapi_sys::{
-    gss_OID, gss_OID_desc, gss_canonicalize_name, gss_display_name, gss_duplicate_name,
-    gss_import_name, gss_name_struct, gss_name_t, gss_release_name, gss_export_name,
-    OM_uint32, GSS_S_COMPLETE,
+    gss_OID, gss_OID_desc, gss_OID_set, gss_OID_set_desc, gss_canonicalize_name,
+    gss_display_name, gss_duplicate_name, gss_import_name, gss_inquire_mechs_for_name,
+    gss_inquire_names_for_mech, gss_name_struct, gss_name_t, gss_release_name,
+    gss_export_name, OM_uint32, GSS_S_COMPLETE,
 };
-#[cfg(feature = "localname")]
+#[cfg(all(feature = "localname", not(gssapi_apple)))]
 use libgssapi_sys::gss_localname;
-#[cfg(feature = "localname")]
+#[cfg(all(feature = "localname", not(gssapi_apple)))]
 use crate::oid::NO_OID;
-use std::{ptr, fmt};
+#[cfg(all(feature = "rfc6680", not(gssapi_apple)))]
+use libgssapi_sys::{gss_buffer_set_t, gss_display_name_ext, gss_inquire_name};
+#[cfg(all(feature = "rfc6680", not(gssapi_apple)))]
+use std::os::raw::c_int;
+use std::{cmp::Ordering, fmt, ptr};
+#[cfg(feature = "serde")]
+use serde::{
+    de::{self, Deserialize, Deserializer, SeqAccess, Unexpected, Visitor},
+    ser::{Serialize, Serializer},
+};
 
 pub struct Name(gss_name_t);
 
@@ -65,7 +75,9 @@ impl Name {
     
     /// parse the specified bytes as a gssapi name, with optional
     /// `kind` e.g. `GSS_NT_HOSTBASED_SERVICE` or
-    /// `GSS_NT_KRB5_PRINCIPAL`.
+    /// `GSS_NT_KRB5_PRINCIPAL`. Names imported with the legacy
+    /// `GSS_NT_HOSTBASED_SERVICE_X` are equivalent to
+    /// `GSS_NT_HOSTBASED_SERVICE` once canonicalized.
     pub fn new(s: &[u8], kind: Option<&Oid>) -> Result<Self, Error> {
         let mut buf = BufRef::from(s);
         let mut minor = GSS_S_COMPLETE;
@@ -91,6 +103,30 @@ impl Name {
         }
     }
 
+    /// Parse the specified bytes as a gssapi name like `new`, and
+    /// canonicalize it for `mech`. The result is a mechanism name,
+    /// ready to be passed to `export`.
+    pub fn new_for_mech(
+        s: &[u8],
+        kind: Option<&Oid>,
+        mech: &Oid,
+    ) -> Result<Self, Error> {
+        Name::new(s, kind)?.canonicalize(Some(mech))
+    }
+
+    /// The anonymous name. Use it as the initiator name when acquiring
+    /// credentials, and request `GSS_C_ANON_FLAG`, to authenticate
+    /// anonymously (e.g. RFC 8062 anonymous Kerberos).
+    pub fn anonymous() -> Result<Self, Error> {
+        Name::new(b"", Some(&GSS_NT_ANONYMOUS))
+    }
+
+    /// True if this is the anonymous name, e.g. the name of a peer
+    /// that authenticated anonymously.
+    pub fn is_anonymous(&self) -> Result<bool, Error> {
+        Ok(self.kind()? == Some(&GSS_NT_ANONYMOUS))
+    }
+
     /// canonicalize a name for the specified mechanism (or the
     /// default mechanism if not specified). This makes a copy of the
     /// name.
@@ -141,6 +177,32 @@ impl Name {
         }
     }
 
+    /// Export the name in the stable form returned by `export`, for
+    /// use as a key in e.g. a database. See `ExportedName`.
+    pub fn exported(&self) -> Result<ExportedName, Error> {
+        Ok(ExportedName(self.export()?.into_vec()))
+    }
+
+    /// Export the name, canonicalizing it for the default mechanism
+    /// first if it isn't a mechanism name.
+    fn export_canonical(&self) -> Result<Buf, Error> {
+        self.export().or_else(|_| self.canonicalize(None)?.export())
+    }
+
+    /// Order two names by their exported form, canonicalizing them
+    /// for the default mechanism first if needed. This gives a total
+    /// order over principals that doesn't depend on how the names were
+    /// imported. Names are compared bytewise by the mechanism specific
+    /// name in the token, then by mechanism, so e.g. `alice@REALM`
+    /// sorts before `bob@REALM`. This is not the order of
+    /// `ExportedName`, which compares whole tokens and so puts shorter
+    /// names first. It isn't an `Ord` impl because it can fail.
+    pub fn cmp_exported(&self, other: &Name) -> Result<Ordering, Error> {
+        let a = self.export_canonical()?;
+        let b = other.export_canonical()?;
+        Ok(exported_key(&a).cmp(&exported_key(&b)))
+    }
+
     /// Return the raw textual representation of the internal GSS
     /// name. Usually this will be utf8, or at least ascii, but that
     /// isn't guaranteed.
@@ -166,10 +228,68 @@ impl Name {
         }
     }
 
+    /// Return the textual representation of the name in the form
+    /// identified by `display_as_type`, rather than the default form
+    /// returned by `display_name`. The supported types depend on the
+    /// mechanism and implementation, unsupported ones fail with
+    /// `GSS_S_UNAVAILABLE`. Asking for the name's own type always
+    /// works. Not available with Apple's GSS framework.
+    #[cfg(all(feature = "rfc6680", not(gssapi_apple)))]
+    pub fn display_as(&self, display_as_type: &Oid) -> Result<Buf, Error> {
+        let mut out = Buf::empty();
+        let mut minor = GSS_S_COMPLETE;
+        let major = unsafe {
+            gss_display_name_ext(
+                &mut minor as *mut OM_uint32,
+                self.to_c(),
+                display_as_type.to_c(),
+                out.to_c(),
+            )
+        };
+        if major == GSS_S_COMPLETE {
+            Ok(out)
+        } else {
+            Err(Error {
+                major: MajorFlags::from_bits_retain(major),
+                minor
+            })
+        }
+    }
+
+    /// Return the name type of this name, e.g. `GSS_NT_KRB5_PRINCIPAL`
+    /// for a name that has been canonicalized to krb5. Returns `None`
+    /// if the implementation doesn't report a type.
+    pub fn kind(&self) -> Result<Option<&'static Oid>, Error> {
+        let mut out = Buf::empty();
+        let mut minor = GSS_S_COMPLETE;
+        let mut oid = ptr::null_mut::<gss_OID_desc>();
+        let major = unsafe {
+            gss_display_name(
+                &mut minor as *mut OM_uint32,
+                self.to_c(),
+                out.to_c(),
+                &mut oid as *mut gss_OID,
+            )
+        };
+        if major == GSS_S_COMPLETE {
+            if oid.is_null() {
+                Ok(None)
+            } else {
+                Ok(Some(unsafe { Oid::from_c(oid) }))
+            }
+        } else {
+            Err(Error {
+                major: MajorFlags::from_bits_retain(major),
+                minor
+            })
+        }
+    }
+
     /// Return the raw textual representation of the internal GSS name
     /// as interpreted by the specified mechanism. If no mechanism is
-    /// specified then it will be assumed to be NO_OID.
-    #[cfg(feature = "localname")]
+    /// specified then it will be assumed to be NO_OID. Not available
+    /// with Apple's GSS framework.
+    #[cfg(all(feature = "localname", not(gssapi_apple)))]
     pub fn local_name(&self, mechs: Option<&Oid>) -> Result<Buf, Error> {
         let mut out = Buf::empty();
         let mut minor = GSS_S_COMPLETE;
@@ -191,6 +311,55 @@ impl Name {
         }
     }
 
+    /// True if this is a mechanism name (MN), i.e. it has been
+    /// canonicalized to a single mechanism, e.g. by `canonicalize` or
+    /// by a context. Only mechanism names can be exported. Not
+    /// available with Apple's GSS framework.
+    #[cfg(all(feature = "rfc6680", not(gssapi_apple)))]
+    pub fn is_mechanism_name(&self) -> Result<bool, Error> {
+        let mut minor = GSS_S_COMPLETE;
+        let mut is_mn: c_int = 0;
+        let major = unsafe {
+            gss_inquire_name(
+                &mut minor as *mut OM_uint32,
+                self.to_c(),
+                &mut is_mn as *mut c_int,
+                ptr::null_mut::<gss_OID>(),
+                ptr::null_mut::<gss_buffer_set_t>(),
+            )
+        };
+        if major == GSS_S_COMPLETE {
+            Ok(is_mn != 0)
+        } else {
+            Err(Error {
+                major: MajorFlags::from_bits_retain(major),
+                minor
+            })
+        }
+    }
+
+    /// Return the set of mechanisms that are able to process this
+    /// name.
+    pub fn mechs_for(&self) -> Result<OidSet, Error> {
+        let mut out = ptr::null_mut::<gss_OID_set_desc>();
+        let mut minor = GSS_S_COMPLETE;
+        let major = unsafe {
+            gss_inquire_mechs_for_name(
+                &mut minor as *mut OM_uint32,
+                self.to_c(),
+                &mut out as *mut gss_OID_set,
+            )
+        };
+        if major == GSS_S_COMPLETE {
+            Ok(unsafe { OidSet::from_c(out) })
+        } else {
+            Err(Error {
+                major: MajorFlags::from_bits_retain(major),
+                minor
+            })
+        }
+    }
+
     /// Duplicate the name.
     pub fn duplicate(&self) -> Result<Self, Error> {
         let mut copy = ptr::null_mut::<gss_name_struct>();
@@ -212,3 +381,312 @@ impl Name {
         }
     }
 }
+
+/// Split an exported name token (RFC 2743 section 3.2, or the
+/// composite form of RFC 6680 section 7.5) into the name, the
+/// mechanism oid, and whatever follows the name (the attributes of a
+/// composite name). Returns `None` if the token is malformed.
+fn split_exported(tok: &[u8]) -> Option<(&[u8], &[u8], &[u8])> {
+    let composite = match tok.get(..2)? {
+        b"\x04\x01" => false,
+        b"\x04\x02" => true,
+        _ => return None,
+    };
+    let rest = &tok[2..];
+    let mech_len = u16::from_be_bytes([*rest.first()?, *rest.get(1)?]) as usize;
+    let mech = rest.get(2..2 + mech_len)?;
+    let rest = &rest[2 + mech_len..];
+    let len = rest.get(..4)?;
+    let name_len = u32::from_be_bytes([len[0], len[1], len[2], len[3]]) as usize;
+    let name = rest.get(4..4usize.checked_add(name_len)?)?;
+    let attrs = &rest[4 + name_len..];
+    if composite || attrs.is_empty() {
+        Some((name, mech, attrs))
+    } else {
+        None
+    }
+}
+
+/// The key `Name::cmp_exported` orders by, the name first, then the
+/// mechanism. Tokens that don't parse are kept whole.
+fn exported_key(tok: &[u8]) -> (&[u8], &[u8], &[u8]) {
+    split_exported(tok).unwrap_or((tok, &[], &[]))
+}
+
+/// A name in the exported form produced by `Name::export`. Unlike a
+/// `Name` this is plain data, it can be compared, hashed, and (with
+/// the `serde` feature) serialized, and later turned back into a
+/// `Name` with `import`.
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct ExportedName(Vec<u8>);
+
+impl ExportedName {
+    /// Wrap bytes previously produced by `Name::export`. They aren't
+    /// checked until the name is imported.
+    pub fn new(bytes: Vec<u8>) -> Self {
+        ExportedName(bytes)
+    }
+
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.0
+    }
+
+    pub fn into_vec(self) -> Vec<u8> {
+        self.0
+    }
+
+    /// Import the name as a mechanism name of type `GSS_NT_EXPORT_NAME`
+    pub fn import(&self) -> Result<Name, Error> {
+        Name::new(&self.0, Some(&GSS_NT_EXPORT_NAME))
+    }
+}
+
+#[cfg(feature = "serde")]
+impl Serialize for ExportedName {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_bytes(&self.0)
+    }
+}
+
+#[cfg(feature = "serde")]
+impl<'de> Deserialize<'de> for ExportedName {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        struct ExportedNameVisitor;
+
+        impl<'de> Visitor<'de> for ExportedNameVisitor {
+            type Value = ExportedName;
+
+            fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+                write!(f, "an exported gssapi name")
+            }
+
+            fn visit_bytes<E: de::Error>(self, v: &[u8]) -> Result<Self::Value, E> {
+                self.visit_byte_buf(v.to_vec())
+            }
+
+            fn visit_byte_buf<E: de::Error>(self, v: Vec<u8>) -> Result<Self::Value, E> {
+                match split_exported(&v) {
+                    Some(_) => Ok(ExportedName(v)),
+                    None => Err(E::invalid_value(Unexpected::Bytes(&v), &self)),
+                }
+            }
+
+            // formats without a native byte string type (e.g. json)
+            // represent bytes as a sequence
+            fn visit_seq<A: SeqAccess<'de>>(
+                self,
+                mut seq: A,
+            ) -> Result<Self::Value, A::Error> {
+                // the hint is untrusted, don't let it allocate much
+                let mut v = Vec::with_capacity(seq.size_hint().unwrap_or(0).min(4096));
+                while let Some(b) = seq.next_element()? {
+                    v.push(b);
+                }
+                self.visit_byte_buf(v)
+            }
+        }
+
+        deserializer.deserialize_byte_buf(ExportedNameVisitor)
+    }
+}
+
+/// Return the set of name types (e.g. `GSS_NT_KRB5_PRINCIPAL`) that
+/// the specified mechanism understands.
+pub fn names_for_mech(mech: &Oid) -> Result<OidSet, Error> {
+    let mut out = ptr::null_mut::<gss_OID_set_desc>();
+    let mut minor = GSS_S_COMPLETE;
+    let major = unsafe {
+        gss_inquire_names_for_mech(
+            &mut minor as *mut OM_uint32,
+            mech.to_c(),
+            &mut out as *mut gss_OID_set,
+        )
+    };
+    if major == GSS_S_COMPLETE {
+        Ok(unsafe { OidSet::from_c(out) })
+    } else {
+        Err(Error {
+            major: MajorFlags::from_bits_retain(major),
+            minor
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::oid::{
+        GSS_MECH_KRB5, GSS_NT_HOSTBASED_SERVICE, GSS_NT_HOSTBASED_SERVICE_X,
+        GSS_NT_KRB5_PRINCIPAL, GSS_NT_USER_NAME,
+    };
+
+    #[test]
+    fn test_hostbased_service_x() {
+        let canonical = |kind| {
+            Name::new(b"host@example.com", Some(kind))
+                .expect("Failed to import name")
+                .canonicalize(Some(&GSS_MECH_KRB5))
+                .expect("Failed to canonicalize name")
+                .export()
+                .expect("Failed to export name")
+        };
+        let current = canonical(&GSS_NT_HOSTBASED_SERVICE);
+        let legacy = canonical(&GSS_NT_HOSTBASED_SERVICE_X);
+        assert_eq!(&*current, &*legacy);
+    }
+
+    #[test]
+    fn test_new_for_mech() {
+        let name = Name::new_for_mech(
+            b"host@example.com",
+            Some(&GSS_NT_HOSTBASED_SERVICE),
+            &GSS_MECH_KRB5,
+        )
+        .expect("Failed to import name");
+        assert_eq!(
+            name.kind().expect("Failed to get kind"),
+            Some(&GSS_NT_KRB5_PRINCIPAL)
+        );
+        let cname = Name::new(b"host@example.com", Some(&GSS_NT_HOSTBASED_SERVICE))
+            .expect("Failed to import name")
+            .canonicalize(Some(&GSS_MECH_KRB5))
+            .expect("Failed to canonicalize name");
+        assert_eq!(
+            &*name.export().expect("Failed to export name"),
+            &*cname.export().expect("Failed to export name")
+        );
+    }
+
+    #[test]
+    fn test_cmp_exported() {
+        let import = |s: &[u8]| {
+            Name::new(s, Some(&GSS_NT_KRB5_PRINCIPAL)).expect("Failed to import name")
+        };
+        let a = import(b"alice@EXAMPLE.COM");
+        let b = import(b"bob@EXAMPLE.COM");
+        let ca = a
+            .canonicalize(Some(&GSS_MECH_KRB5))
+            .expect("Failed to canonicalize name");
+        assert_eq!(a.cmp_exported(&b).expect("Failed to compare"), Ordering::Less);
+        assert_eq!(b.cmp_exported(&a).expect("Failed to compare"), Ordering::Greater);
+        assert_eq!(a.cmp_exported(&ca).expect("Failed to compare"), Ordering::Equal);
+        // the whole tokens order by length first
+        let ea = a.exported().expect("Failed to export name");
+        let eb = b.exported().expect("Failed to export name");
+        assert!(eb < ea);
+    }
+
+    #[test]
+    fn test_exported_key() {
+        let tok: &[u8] = b"\x04\x01\x00\x0b\x06\x09\x2a\x86\x48\x86\xf7\x12\x01\x02\x02\
+                    \x00\x00\x00\x03bob";
+        let empty = &b""[..];
+        assert_eq!(exported_key(tok), (&b"bob"[..], &tok[4..15], empty));
+        let truncated = &tok[..tok.len() - 1];
+        assert_eq!(exported_key(truncated), (truncated, empty, empty));
+        let trailing = [tok, &b"x"[..]].concat();
+        assert_eq!(exported_key(&trailing), (&trailing[..], empty, empty));
+        assert_eq!(exported_key(b"\x04\x03"), (&b"\x04\x03"[..], empty, empty));
+    }
+
+    #[test]
+    fn test_mechs_for() {
+        let name =
+            Name::new(b"nobody", Some(&GSS_NT_USER_NAME)).expect("Failed to import name");
+        let mechs = name.mechs_for().expect("Failed to inquire mechs for name");
+        assert!(mechs.contains(&GSS_MECH_KRB5).unwrap());
+    }
+
+    #[test]
+    fn test_names_for_mech() {
+        let kinds = names_for_mech(&GSS_MECH_KRB5).expect("Failed to inquire names");
+        assert!(kinds.contains(&GSS_NT_KRB5_PRINCIPAL).unwrap());
+        assert!(kinds.contains(&GSS_NT_HOSTBASED_SERVICE).unwrap());
+    }
+
+    #[test]
+    fn test_kind() {
+        let name = Name::new(b"nobody@EXAMPLE.COM", Some(&GSS_NT_USER_NAME))
+            .expect("Failed to import name");
+        assert_eq!(
+            name.kind().expect("Failed to get kind"),
+            Some(&GSS_NT_USER_NAME)
+        );
+        let cname = name
+            .canonicalize(Some(&GSS_MECH_KRB5))
+            .expect("Failed to canonicalize name");
+        assert_eq!(
+            cname.kind().expect("Failed to get kind"),
+            Some(&GSS_NT_KRB5_PRINCIPAL)
+        );
+    }
+
+    #[cfg(all(feature = "rfc6680", not(gssapi_apple)))]
+    #[test]
+    fn test_is_mechanism_name() {
+        let name = Name::new(b"nobody@EXAMPLE.COM", Some(&GSS_NT_USER_NAME))
+            .expect("Failed to import name");
+        assert!(!name.is_mechanism_name().expect("Failed to inquire name"));
+        let cname = name
+            .canonicalize(Some(&GSS_MECH_KRB5))
+            .expect("Failed to canonicalize name");
+        assert!(cname.is_mechanism_name().expect("Failed to inquire name"));
+    }
+
+    #[cfg(all(feature = "rfc6680", not(gssapi_apple)))]
+    #[test]
+    fn test_display_as() {
+        let princ = b"host/example.com@EXAMPLE.COM";
+        let name = Name::new(princ, Some(&GSS_NT_KRB5_PRINCIPAL))
+            .expect("Failed to import name")
+            .canonicalize(Some(&GSS_MECH_KRB5))
+            .expect("Failed to canonicalize name");
+        let buf = name
+            .display_as(&GSS_NT_KRB5_PRINCIPAL)
+            .expect("Failed to display name");
+        assert_eq!(&*buf, princ);
+        name.display_as(&GSS_NT_ANONYMOUS)
+            .expect_err("Expected error displaying as an unsupported type");
+    }
+
+    #[test]
+    fn test_anonymous() {
+        let name = Name::anonymous().expect("Failed to import anonymous name");
+        assert!(name.is_anonymous().expect("Failed to get kind"));
+        let name =
+            Name::new(b"nobody", Some(&GSS_NT_USER_NAME)).expect("Failed to import name");
+        assert!(!name.is_anonymous().expect("Failed to get kind"));
+    }
+
+    #[test]
+    fn test_exported_name() {
+        let name = Name::new(b"nobody@EXAMPLE.COM", Some(&GSS_NT_KRB5_PRINCIPAL))
+            .expect("Failed to import name")
+            .canonicalize(Some(&GSS_MECH_KRB5))
+            .expect("Failed to canonicalize name");
+        let exported = name.exported().expect("Failed to export name");
+        assert_eq!(exported.as_bytes(), &*name.export().expect("Failed to export name"));
+        let imported = ExportedName::new(exported.as_bytes().to_vec())
+            .import()
+            .expect("Failed to import exported name");
+        assert_eq!(imported.exported().expect("Failed to export name"), exported);
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn test_exported_name_serde() {
+        let name = Name::new(b"nobody@EXAMPLE.COM", Some(&GSS_NT_KRB5_PRINCIPAL))
+            .expect("Failed to import name")
+            .canonicalize(Some(&GSS_MECH_KRB5))
+            .expect("Failed to canonicalize name");
+        let exported = name.exported().expect("Failed to export name");
+        let json = serde_json::to_string(&exported).expect("Failed to serialize");
+        let de: ExportedName = serde_json::from_str(&json).expect("Failed to deserialize");
+        assert_eq!(de, exported);
+        assert!(serde_json::from_str::<ExportedName>("[1,2,3]").is_err());
+        let mut truncated = exported.into_vec();
+        truncated.pop();
+        let json = serde_json::to_string(&truncated).expect("Failed to serialize");
+        assert!(serde_json::from_str::<ExportedName>(&json).is_err());
+    }
+}
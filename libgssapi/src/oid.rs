@@ -1,25 +1,34 @@
 /// Oids are BER encoded and defined in the various RFCs. Oids are
 /// horrible. This module is horrible. I'm so pleased to share my
 /// horror with you.
-use crate::error::{Error, MajorFlags};
+use crate::{
+    error::{Error, MajorFlags},
+    util::{Buf, BufRef},
+};
+#[cfg(feature = "rfc5587")]
+use libgssapi_sys::{gss_display_mech_attr, gss_inquire_attrs_for_mech};
+#[cfg(feature = "rfc5801")]
+use libgssapi_sys::{gss_inquire_mech_for_saslname, gss_inquire_saslname_for_mech};
 use libgssapi_sys::{
     gss_OID, gss_OID_desc, gss_OID_set, gss_OID_set_desc, gss_add_oid_set_member,
-    gss_create_empty_oid_set, gss_release_oid_set, gss_test_oid_set_member, OM_uint32,
+    gss_create_empty_oid_set, gss_indicate_mechs, gss_oid_to_str, gss_release_oid,
+    gss_release_oid_set, gss_str_to_oid, gss_test_oid_set_member, OM_uint32,
     GSS_S_COMPLETE,
 };
 use std::{
     self,
     cmp::{Eq, Ord, Ordering, PartialEq, PartialOrd},
-    collections::HashMap,
+    collections::{HashMap, HashSet},
     fmt,
     hash::{Hash, Hasher},
     iter::{ExactSizeIterator, FromIterator, IntoIterator, Iterator},
     ops::{Deref, Index},
     ptr, slice,
     os::raw::c_int,
+    str::FromStr,
+    sync::Mutex,
 };
 
-// CR estokes: do I need the attributes from rfc 5587? There are loads of them.
 pub static GSS_NT_USER_NAME: Oid =
     Oid::from_slice(b"\x2a\x86\x48\x86\xf7\x12\x01\x02\x01\x01");
 
@@ -32,7 +41,11 @@ pub static GSS_NT_STRING_UID_NAME: Oid =
 pub static GSS_NT_HOSTBASED_SERVICE: Oid =
     Oid::from_slice(b"\x2a\x86\x48\x86\xf7\x12\x01\x02\x01\x04");
 
-pub static GSS_NT_ANONYMOUS: Oid = Oid::from_slice(b"\x2b\x06\01\x05\x06\x03");
+/// The deprecated oid some older implementations use for hostbased
+/// service names, it is treated the same as `GSS_NT_HOSTBASED_SERVICE`.
+pub static GSS_NT_HOSTBASED_SERVICE_X: Oid = Oid::from_slice(b"\x2b\x06\x01\x05\x06\x02");
+
+pub static GSS_NT_ANONYMOUS: Oid = Oid::from_slice(b"\x2b\x06\x01\x05\x06\x03");
 
 pub static GSS_NT_EXPORT_NAME: Oid = Oid::from_slice(b"\x2b\x06\x01\x05\x06\x04");
 
@@ -56,6 +69,61 @@ pub static GSS_INQ_NEGOEX_VERIFY_KEY: Oid =
 pub static GSS_MA_NEGOEX_AND_SPNEGO: Oid =
     Oid::from_slice(b"\x2a\x86\x48\x86\xf7\x12\x01\x02\x02\x05\x12");
 
+// mechanism attributes from rfc 5587
+pub static GSS_MA_MECH_CONCRETE: Oid = Oid::from_slice(b"\x2b\x06\x01\x05\x05\x0d\x01");
+
+pub static GSS_MA_MECH_PSEUDO: Oid = Oid::from_slice(b"\x2b\x06\x01\x05\x05\x0d\x02");
+
+pub static GSS_MA_MECH_COMPOSITE: Oid = Oid::from_slice(b"\x2b\x06\x01\x05\x05\x0d\x03");
+
+pub static GSS_MA_MECH_NEGO: Oid = Oid::from_slice(b"\x2b\x06\x01\x05\x05\x0d\x04");
+
+pub static GSS_MA_MECH_GLUE: Oid = Oid::from_slice(b"\x2b\x06\x01\x05\x05\x0d\x05");
+
+pub static GSS_MA_NOT_MECH: Oid = Oid::from_slice(b"\x2b\x06\x01\x05\x05\x0d\x06");
+
+pub static GSS_MA_DEPRECATED: Oid = Oid::from_slice(b"\x2b\x06\x01\x05\x05\x0d\x07");
+
+pub static GSS_MA_NOT_DFLT_MECH: Oid = Oid::from_slice(b"\x2b\x06\x01\x05\x05\x0d\x08");
+
+pub static GSS_MA_ITOK_FRAMED: Oid = Oid::from_slice(b"\x2b\x06\x01\x05\x05\x0d\x09");
+
+pub static GSS_MA_AUTH_INIT: Oid = Oid::from_slice(b"\x2b\x06\x01\x05\x05\x0d\x0a");
+
+pub static GSS_MA_AUTH_TARG: Oid = Oid::from_slice(b"\x2b\x06\x01\x05\x05\x0d\x0b");
+
+pub static GSS_MA_AUTH_INIT_INIT: Oid = Oid::from_slice(b"\x2b\x06\x01\x05\x05\x0d\x0c");
+
+pub static GSS_MA_AUTH_TARG_INIT: Oid = Oid::from_slice(b"\x2b\x06\x01\x05\x05\x0d\x0d");
+
+pub static GSS_MA_AUTH_INIT_ANON: Oid = Oid::from_slice(b"\x2b\x06\x01\x05\x05\x0d\x0e");
+
+pub static GSS_MA_AUTH_TARG_ANON: Oid = Oid::from_slice(b"\x2b\x06\x01\x05\x05\x0d\x0f");
+
+pub static GSS_MA_DELEG_CRED: Oid = Oid::from_slice(b"\x2b\x06\x01\x05\x05\x0d\x10");
+
+pub static GSS_MA_INTEG_PROT: Oid = Oid::from_slice(b"\x2b\x06\x01\x05\x05\x0d\x11");
+
+pub static GSS_MA_CONF_PROT: Oid = Oid::from_slice(b"\x2b\x06\x01\x05\x05\x0d\x12");
+
+pub static GSS_MA_MIC: Oid = Oid::from_slice(b"\x2b\x06\x01\x05\x05\x0d\x13");
+
+pub static GSS_MA_WRAP: Oid = Oid::from_slice(b"\x2b\x06\x01\x05\x05\x0d\x14");
+
+pub static GSS_MA_PROT_READY: Oid = Oid::from_slice(b"\x2b\x06\x01\x05\x05\x0d\x15");
+
+pub static GSS_MA_REPLAY_DET: Oid = Oid::from_slice(b"\x2b\x06\x01\x05\x05\x0d\x16");
+
+pub static GSS_MA_OOS_DET: Oid = Oid::from_slice(b"\x2b\x06\x01\x05\x05\x0d\x17");
+
+pub static GSS_MA_CBINDINGS: Oid = Oid::from_slice(b"\x2b\x06\x01\x05\x05\x0d\x18");
+
+pub static GSS_MA_PFS: Oid = Oid::from_slice(b"\x2b\x06\x01\x05\x05\x0d\x19");
+
+pub static GSS_MA_COMPRESS: Oid = Oid::from_slice(b"\x2b\x06\x01\x05\x05\x0d\x1a");
+
+pub static GSS_MA_CTX_TRANS: Oid = Oid::from_slice(b"\x2b\x06\x01\x05\x05\x0d\x1b");
+
 pub static GSS_SEC_CONTEXT_SASL_SSF: Oid =
     Oid::from_slice(b"\x2a\x86\x48\x86\xf7\x12\x01\x02\x02\x05\x0f");
 
@@ -71,6 +139,18 @@ pub static GSS_KRB5_CRED_NO_CI_FLAGS_X: Oid =
 pub static GSS_KRB5_GET_CRED_IMPERSONATOR: Oid =
     Oid::from_slice(b"\x2a\x86\x48\x86\xf7\x12\x01\x02\x02\x05\x0e");
 
+/// The oids most programs need, for a single glob import with
+/// `use libgssapi::oid::consts::*`. These are the same statics as in
+/// the parent module, the `GSS_C_` spelling of the anonymous name type
+/// matches the C headers.
+pub mod consts {
+    pub use super::{
+        GSS_MECH_IAKERB, GSS_MECH_KRB5, GSS_MECH_SPNEGO, GSS_NT_ANONYMOUS,
+        GSS_NT_ANONYMOUS as GSS_C_NT_ANONYMOUS, GSS_NT_HOSTBASED_SERVICE,
+        GSS_NT_KRB5_PRINCIPAL, GSS_NT_USER_NAME,
+    };
+}
+
 pub(crate) const NO_OID: gss_OID = ptr::null_mut();
 pub(crate) const NO_OID_SET: gss_OID_set = ptr::null_mut();
 
@@ -81,6 +161,7 @@ lazy_static! {
             (GSS_NT_MACHINE_UID_NAME, "GSS_NT_MACHINE_UID_NAME"),
             (GSS_NT_STRING_UID_NAME, "GSS_NT_STRING_UID_NAME"),
             (GSS_NT_HOSTBASED_SERVICE, "GSS_NT_HOSTBASED_SERVICE"),
+            (GSS_NT_HOSTBASED_SERVICE_X, "GSS_NT_HOSTBASED_SERVICE_X"),
             (GSS_NT_ANONYMOUS, "GSS_NT_ANONYMOUS"),
             (GSS_NT_EXPORT_NAME, "GSS_NT_EXPORT_NAME"),
             (GSS_NT_COMPOSITE_EXPORT, "GSS_NT_COMPOSITE_EXPORT"),
@@ -88,6 +169,33 @@ lazy_static! {
             (GSS_INQ_NEGOEX_KEY, "GSS_INQ_NEGOEX_KEY"),
             (GSS_INQ_NEGOEX_VERIFY_KEY, "GSS_INQ_NEGOEX_VERIFY_KEY"),
             (GSS_MA_NEGOEX_AND_SPNEGO, "GSS_MA_NEGOEX_AND_SPNEGO"),
+            (GSS_MA_MECH_CONCRETE, "GSS_MA_MECH_CONCRETE"),
+            (GSS_MA_MECH_PSEUDO, "GSS_MA_MECH_PSEUDO"),
+            (GSS_MA_MECH_COMPOSITE, "GSS_MA_MECH_COMPOSITE"),
+            (GSS_MA_MECH_NEGO, "GSS_MA_MECH_NEGO"),
+            (GSS_MA_MECH_GLUE, "GSS_MA_MECH_GLUE"),
+            (GSS_MA_NOT_MECH, "GSS_MA_NOT_MECH"),
+            (GSS_MA_DEPRECATED, "GSS_MA_DEPRECATED"),
+            (GSS_MA_NOT_DFLT_MECH, "GSS_MA_NOT_DFLT_MECH"),
+            (GSS_MA_ITOK_FRAMED, "GSS_MA_ITOK_FRAMED"),
+            (GSS_MA_AUTH_INIT, "GSS_MA_AUTH_INIT"),
+            (GSS_MA_AUTH_TARG, "GSS_MA_AUTH_TARG"),
+            (GSS_MA_AUTH_INIT_INIT, "GSS_MA_AUTH_INIT_INIT"),
+            (GSS_MA_AUTH_TARG_INIT, "GSS_MA_AUTH_TARG_INIT"),
+            (GSS_MA_AUTH_INIT_ANON, "GSS_MA_AUTH_INIT_ANON"),
+            (GSS_MA_AUTH_TARG_ANON, "GSS_MA_AUTH_TARG_ANON"),
+            (GSS_MA_DELEG_CRED, "GSS_MA_DELEG_CRED"),
+            (GSS_MA_INTEG_PROT, "GSS_MA_INTEG_PROT"),
+            (GSS_MA_CONF_PROT, "GSS_MA_CONF_PROT"),
+            (GSS_MA_MIC, "GSS_MA_MIC"),
+            (GSS_MA_WRAP, "GSS_MA_WRAP"),
+            (GSS_MA_PROT_READY, "GSS_MA_PROT_READY"),
+            (GSS_MA_REPLAY_DET, "GSS_MA_REPLAY_DET"),
+            (GSS_MA_OOS_DET, "GSS_MA_OOS_DET"),
+            (GSS_MA_CBINDINGS, "GSS_MA_CBINDINGS"),
+            (GSS_MA_PFS, "GSS_MA_PFS"),
+            (GSS_MA_COMPRESS, "GSS_MA_COMPRESS"),
+            (GSS_MA_CTX_TRANS, "GSS_MA_CTX_TRANS"),
             (GSS_SEC_CONTEXT_SASL_SSF, "GSS_SEC_CONTEXT_SASL_SSF"),
             (GSS_MECH_KRB5, "GSS_MECH_KRB5"),
             (GSS_MECH_IAKERB, "GSS_MECH_IAKERB"),
@@ -102,6 +210,11 @@ lazy_static! {
         .iter()
         .copied()
     );
+
+    // Oids parsed from strings must live in static memory like all
+    // the others. They are interned here so that parsing the same
+    // oid over and over doesn't leak.
+    static ref PARSED: Mutex<HashSet<&'static [u8]>> = Mutex::new(HashSet::new());
 }
 
 /* I've copied lots of OIDs from lots of standards into this module in
@@ -131,9 +244,78 @@ impl fmt::Debug for Oid {
     }
 }
 
+/// Display the oid in dotted decimal form, e.g. `1.2.840.113554.1.2.2`
 impl fmt::Display for Oid {
     fn fmt(&self, f: &mut fmt::Formatter) -> Result<(), fmt::Error> {
-        fmt::Debug::fmt(self, f)
+        let mut minor = GSS_S_COMPLETE;
+        let mut buf = Buf::empty();
+        let major = unsafe {
+            gss_oid_to_str(&mut minor as *mut OM_uint32, self.to_c(), buf.to_c())
+        };
+        if major == GSS_S_COMPLETE {
+            // gssapi renders oids as "{ 1 2 840 113554 1 2 2 }"
+            let s = String::from_utf8_lossy(&buf);
+            let s = s.trim_matches(|c: char| {
+                c == '{' || c == '}' || c.is_whitespace() || c == '\0'
+            });
+            write!(f, "{}", s.split_whitespace().collect::<Vec<_>>().join("."))
+        } else {
+            fmt::Debug::fmt(self, f)
+        }
+    }
+}
+
+/// Parse an oid in either dotted decimal form,
+/// e.g. `1.2.840.113554.1.2.2`, or the bracketed form used by
+/// gssapi itself, e.g. `{ 1 2 840 113554 1 2 2 }`. The form is
+/// detected by the presence of the braces. Anything else, including
+/// empty components, fails with `GSS_S_FAILURE`. Like every `Oid`
+/// the parsed oid must live in static memory, so each distinct oid
+/// parsed is kept until the process exits.
+impl FromStr for Oid {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        // gss_str_to_oid only reliably accepts the "{ 1 2 840 }" form,
+        // and is lenient about what it skips, so check it ourselves
+        let s = s.trim();
+        let components = match s.strip_prefix('{').and_then(|s| s.strip_suffix('}')) {
+            Some(inner) => inner.split_whitespace().collect::<Vec<_>>(),
+            None => s.split('.').collect::<Vec<_>>(),
+        };
+        let valid = components.len() >= 2
+            && components
+                .iter()
+                .all(|c| !c.is_empty() && c.bytes().all(|b| b.is_ascii_digit()));
+        if !valid {
+            return Err(Error {
+                major: MajorFlags::GSS_S_FAILURE,
+                minor: 0,
+            });
+        }
+        let s = format!("{{ {} }}", components.join(" "));
+        let mut buf = BufRef::from(s.as_bytes());
+        let mut minor = GSS_S_COMPLETE;
+        let mut oid = ptr::null_mut::<gss_OID_desc>();
+        let major = unsafe {
+            gss_str_to_oid(
+                &mut minor as *mut OM_uint32,
+                buf.to_c(),
+                &mut oid as *mut gss_OID,
+            )
+        };
+        if major == GSS_S_COMPLETE {
+            let ber = unsafe { Oid::from_c(oid) }.to_vec();
+            let _major = unsafe {
+                gss_release_oid(&mut minor as *mut OM_uint32, &mut oid as *mut gss_OID)
+            };
+            Ok(Oid::intern(ber))
+        } else {
+            Err(Error {
+                major: MajorFlags::from_bits_retain(major),
+                minor,
+            })
+        }
     }
 }
 
@@ -149,17 +331,33 @@ impl Deref for Oid {
     }
 }
 
+/// Oids are compared by their BER encoding, never by address, so an
+/// oid returned by the library is equal to the corresponding static
+/// here. Owned and borrowed oids can be compared directly, e.g.
+/// `ctx.mech()? == &GSS_MECH_KRB5`.
 impl PartialEq for Oid {
     fn eq(&self, other: &Oid) -> bool {
         &*self as &[u8] == &*other as &[u8]
     }
 }
 
+impl<'a> PartialEq<&'a Oid> for Oid {
+    fn eq(&self, other: &&'a Oid) -> bool {
+        self == *other
+    }
+}
+
+impl<'a> PartialEq<Oid> for &'a Oid {
+    fn eq(&self, other: &Oid) -> bool {
+        *self == other
+    }
+}
+
 impl Eq for Oid {}
 
 impl PartialOrd for Oid {
     fn partial_cmp(&self, other: &Oid) -> Option<Ordering> {
-        (&*self as &[u8]).partial_cmp(&*other as &[u8])
+        Some(self.cmp(other))
     }
 }
 
@@ -205,6 +403,18 @@ impl Oid {
         let elements = ber.as_ptr() as *mut std::ffi::c_void;
         Oid(gss_OID_desc { length, elements })
     }
+
+    fn intern(ber: Vec<u8>) -> Oid {
+        let mut parsed = PARSED.lock().unwrap();
+        match parsed.get(&ber[..]) {
+            Some(ber) => Oid::from_slice(ber),
+            None => {
+                let ber: &'static [u8] = Box::leak(ber.into_boxed_slice());
+                parsed.insert(ber);
+                Oid::from_slice(ber)
+            }
+        }
+    }
 }
 
 pub struct OidSetIter<'a> {
@@ -216,7 +426,7 @@ impl<'a> Iterator for OidSetIter<'a> {
     type Item = &'a Oid;
 
     fn next(&mut self) -> Option<Self::Item> {
-        if self.current < self.len() {
+        if self.current < self.set.len() {
             let res = Some(&self.set[self.current]);
             self.current += 1;
             res
@@ -282,6 +492,30 @@ impl<'a> IntoIterator for &'a OidSet {
     }
 }
 
+/// Collect oids into a new set. Adding a member to a set only fails
+/// if memory can't be allocated, in which case this will panic. No
+/// member is ever silently dropped.
+impl<'a> FromIterator<&'a Oid> for OidSet {
+    fn from_iter<T: IntoIterator<Item = &'a Oid>>(iter: T) -> Self {
+        let mut set = OidSet::new().expect("failed to allocate oid set");
+        for id in iter {
+            set.add(id).expect("failed to add oid to set");
+        }
+        set
+    }
+}
+
+/// Collect oids into a new set. See the `&Oid` implementation.
+impl FromIterator<Oid> for OidSet {
+    fn from_iter<T: IntoIterator<Item = Oid>>(iter: T) -> Self {
+        let mut set = OidSet::new().expect("failed to allocate oid set");
+        for id in iter {
+            set.add(&id).expect("failed to add oid to set");
+        }
+        set
+    }
+}
+
 impl fmt::Debug for OidSet {
     fn fmt(&self, f: &mut fmt::Formatter) -> Result<(), fmt::Error> {
         fmt::Debug::fmt(&self.into_iter().collect::<Vec<_>>(), f)
@@ -310,6 +544,12 @@ impl OidSet {
         }
     }
 
+    /// Create an empty OID set, the same as `new`. Build it up with
+    /// `add`, or collect one from an iterator of oids.
+    pub fn empty() -> Result<OidSet, Error> {
+        OidSet::new()
+    }
+
     #[allow(dead_code)]
     pub(crate) unsafe fn from_c(ptr: gss_OID_set) -> OidSet {
         OidSet(ptr)
@@ -324,7 +564,13 @@ impl OidSet {
         unsafe { (*self.0).count as usize }
     }
 
-    /// Add an OID to the set.
+    /// True if there are no oids in this set
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Add an OID to the set. The set stores a copy of the oid, so it
+    /// doesn't borrow `id`.
     pub fn add(&mut self, id: &Oid) -> Result<(), Error> {
         let mut minor = GSS_S_COMPLETE;
         let major = unsafe {
@@ -367,3 +613,315 @@ impl OidSet {
         }
     }
 }
+
+/// Ask gssapi which mechanisms it supports.
+pub fn indicate_mechs() -> Result<OidSet, Error> {
+    let mut minor = GSS_S_COMPLETE;
+    let mut out = ptr::null_mut::<gss_OID_set_desc>();
+    let major = unsafe {
+        gss_indicate_mechs(&mut minor as *mut OM_uint32, &mut out as *mut gss_OID_set)
+    };
+    if major == GSS_S_COMPLETE {
+        Ok(OidSet(out))
+    } else {
+        Err(Error {
+            major: MajorFlags::from_bits_retain(major),
+            minor,
+        })
+    }
+}
+
+/// Return the set of attributes (e.g. `GSS_MA_DELEG_CRED`) the
+/// specified mechanism has, and the set of all attributes it knows
+/// about, in that order.
+#[cfg(feature = "rfc5587")]
+pub fn mech_attrs(mech: &Oid) -> Result<(OidSet, OidSet), Error> {
+    let mut minor = GSS_S_COMPLETE;
+    let mut attrs = ptr::null_mut::<gss_OID_set_desc>();
+    let mut known = ptr::null_mut::<gss_OID_set_desc>();
+    let major = unsafe {
+        gss_inquire_attrs_for_mech(
+            &mut minor as *mut OM_uint32,
+            mech.to_c(),
+            &mut attrs as *mut gss_OID_set,
+            &mut known as *mut gss_OID_set,
+        )
+    };
+    // we must take ownership of whatever was allocated
+    let (attrs, known) = (OidSet(attrs), OidSet(known));
+    if major == GSS_S_COMPLETE {
+        Ok((attrs, known))
+    } else {
+        Err(Error {
+            major: MajorFlags::from_bits_retain(major),
+            minor,
+        })
+    }
+}
+
+/// The human readable description of a mechanism attribute
+#[cfg(feature = "rfc5587")]
+#[derive(Debug)]
+pub struct MechAttrInfo {
+    pub name: Buf,
+    pub short_desc: Buf,
+    pub long_desc: Buf,
+}
+
+/// Describe the specified mechanism attribute
+#[cfg(feature = "rfc5587")]
+pub fn display_mech_attr(attr: &Oid) -> Result<MechAttrInfo, Error> {
+    let mut minor = GSS_S_COMPLETE;
+    let mut name = Buf::empty();
+    let mut short_desc = Buf::empty();
+    let mut long_desc = Buf::empty();
+    let major = unsafe {
+        gss_display_mech_attr(
+            &mut minor as *mut OM_uint32,
+            attr.to_c(),
+            name.to_c(),
+            short_desc.to_c(),
+            long_desc.to_c(),
+        )
+    };
+    if major == GSS_S_COMPLETE {
+        Ok(MechAttrInfo {
+            name,
+            short_desc,
+            long_desc,
+        })
+    } else {
+        Err(Error {
+            major: MajorFlags::from_bits_retain(major),
+            minor,
+        })
+    }
+}
+
+/// The RFC 5801 (GS2) description of a mechanism
+#[cfg(feature = "rfc5801")]
+#[derive(Debug)]
+pub struct SaslMechInfo {
+    /// The SASL mechanism name, e.g. `GS2-KRB5`
+    pub sasl_name: Buf,
+    pub mech_name: Buf,
+    pub mech_description: Buf,
+}
+
+/// Return the SASL name of the specified mechanism, along with its
+/// name and description, for bridging it into SASL with GS2.
+#[cfg(feature = "rfc5801")]
+pub fn sasl_name_for_mech(mech: &Oid) -> Result<SaslMechInfo, Error> {
+    let mut minor = GSS_S_COMPLETE;
+    let mut sasl_name = Buf::empty();
+    let mut mech_name = Buf::empty();
+    let mut mech_description = Buf::empty();
+    let major = unsafe {
+        gss_inquire_saslname_for_mech(
+            &mut minor as *mut OM_uint32,
+            mech.to_c(),
+            sasl_name.to_c(),
+            mech_name.to_c(),
+            mech_description.to_c(),
+        )
+    };
+    if major == GSS_S_COMPLETE {
+        Ok(SaslMechInfo {
+            sasl_name,
+            mech_name,
+            mech_description,
+        })
+    } else {
+        Err(Error {
+            major: MajorFlags::from_bits_retain(major),
+            minor,
+        })
+    }
+}
+
+/// Return the mechanism with the specified SASL name, e.g. `GS2-KRB5`
+#[cfg(feature = "rfc5801")]
+pub fn mech_for_sasl_name(name: &[u8]) -> Result<Oid, Error> {
+    let mut minor = GSS_S_COMPLETE;
+    let mut name = BufRef::from(name);
+    let mut mech = NO_OID;
+    let major = unsafe {
+        gss_inquire_mech_for_saslname(
+            &mut minor as *mut OM_uint32,
+            name.to_c(),
+            &mut mech as *mut gss_OID,
+        )
+    };
+    if major == GSS_S_COMPLETE && mech.is_null() {
+        Err(Error {
+            major: MajorFlags::GSS_S_BAD_MECH,
+            minor,
+        })
+    } else if major == GSS_S_COMPLETE {
+        // the mechanism oid is in static memory owned by the library
+        Ok(unsafe { *Oid::from_c(mech) })
+    } else {
+        Err(Error {
+            major: MajorFlags::from_bits_retain(major),
+            minor,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // call f with a copy of GSS_MECH_KRB5 that lives on the heap, and
+    // so has a different address than the static, only until f returns
+    fn with_krb5_copy<R>(f: impl FnOnce(&Oid) -> R) -> R {
+        let bytes = GSS_MECH_KRB5.to_vec();
+        let krb5 = Oid::from(gss_OID_desc {
+            length: bytes.len() as u32,
+            elements: bytes.as_ptr() as *mut std::ffi::c_void,
+        });
+        f(&krb5)
+    }
+
+    #[test]
+    fn test_collect() {
+        let set: OidSet = vec![GSS_MECH_KRB5, GSS_MECH_SPNEGO].into_iter().collect();
+        assert_eq!(set.len(), 2);
+        assert!(set.contains(&GSS_MECH_KRB5).unwrap());
+        assert!(set.contains(&GSS_MECH_SPNEGO).unwrap());
+        assert!(!set.contains(&GSS_MECH_IAKERB).unwrap());
+        let copy: OidSet = set.into_iter().collect();
+        assert_eq!(
+            copy.into_iter().collect::<Vec<_>>(),
+            vec![&GSS_MECH_KRB5, &GSS_MECH_SPNEGO]
+        );
+    }
+
+    #[test]
+    fn test_empty_add() {
+        let mut set = OidSet::empty().expect("Failed to create oid set");
+        assert!(set.is_empty());
+        with_krb5_copy(|krb5| set.add(krb5)).expect("Failed to add oid");
+        assert_eq!(set.len(), 1);
+        assert_eq!(set[0], GSS_MECH_KRB5);
+        assert!(set.contains(&GSS_MECH_KRB5).unwrap());
+    }
+
+    #[test]
+    fn test_consts() {
+        use crate::name::Name;
+        use consts::*;
+        Name::new(b"host@example.com", Some(&GSS_NT_HOSTBASED_SERVICE))
+            .expect("Failed to import name");
+        let name = Name::anonymous().expect("Failed to import anonymous name");
+        assert_eq!(
+            name.kind().expect("Failed to get kind"),
+            Some(&GSS_C_NT_ANONYMOUS)
+        );
+        let set: OidSet = [&GSS_MECH_KRB5, &GSS_MECH_SPNEGO, &GSS_MECH_IAKERB]
+            .iter()
+            .copied()
+            .collect();
+        assert_eq!(set.len(), 3);
+    }
+
+    #[test]
+    fn test_dotted_decimal() {
+        let krb5: Oid = "1.2.840.113554.1.2.2".parse().expect("Failed to parse oid");
+        assert_eq!(krb5, GSS_MECH_KRB5);
+        assert_eq!(GSS_MECH_KRB5.to_string(), "1.2.840.113554.1.2.2");
+        assert_eq!(GSS_MECH_SPNEGO.to_string(), "1.3.6.1.5.5.2");
+        let again: Oid = "1.2.840.113554.1.2.2".parse().unwrap();
+        assert_eq!(again.as_ptr(), krb5.as_ptr());
+        "1.2.banana"
+            .parse::<Oid>()
+            .expect_err("Expected error parsing a bad oid");
+    }
+
+    #[test]
+    fn test_eq() {
+        with_krb5_copy(|krb5| {
+            assert_ne!(krb5.as_ptr(), GSS_MECH_KRB5.as_ptr());
+            assert_eq!(*krb5, GSS_MECH_KRB5);
+            assert!(*krb5 == &GSS_MECH_KRB5);
+            assert!(krb5 == GSS_MECH_KRB5);
+            assert!(*krb5 != GSS_MECH_SPNEGO);
+            let set: HashSet<Oid> =
+                [GSS_MECH_KRB5, GSS_MECH_SPNEGO].iter().copied().collect();
+            assert!(set.contains(krb5));
+            assert_eq!(format!("{:?}", krb5), "GSS_MECH_KRB5");
+        });
+    }
+
+    #[test]
+    fn test_bracketed() {
+        let dotted: Oid = "1.2.840.113554.1.2.2".parse().expect("Failed to parse oid");
+        let bracketed: Oid = "{ 1 2 840 113554 1 2 2 }"
+            .parse()
+            .expect("Failed to parse oid");
+        assert_eq!(dotted, bracketed);
+        assert_eq!(bracketed, GSS_MECH_KRB5);
+        let tight: Oid = "{1 3 6 1 5 5 2}".parse().expect("Failed to parse oid");
+        assert_eq!(tight, GSS_MECH_SPNEGO);
+    }
+
+    #[test]
+    fn test_parse_invalid() {
+        for s in [
+            "",
+            "1",
+            "1..2",
+            "1.2.",
+            ".1.2",
+            "1 2 840",
+            "1.2} {3",
+            "1.-2",
+            "1.+2",
+            "{ 1 2 banana }",
+            "{ 1.2.840 }",
+            "{ 1 2} {3 }",
+            "{ }",
+        ] {
+            let e = s.parse::<Oid>().expect_err(s);
+            assert_eq!(e.major, MajorFlags::GSS_S_FAILURE);
+        }
+    }
+
+    #[test]
+    fn test_indicate_mechs() {
+        let mechs = indicate_mechs().expect("Failed to indicate mechs");
+        assert!(mechs.contains(&GSS_MECH_KRB5).unwrap());
+        assert!(mechs.into_iter().any(|m| m == &GSS_MECH_KRB5));
+    }
+
+    #[cfg(feature = "rfc5587")]
+    #[test]
+    fn test_mech_attrs() {
+        let (attrs, known) = mech_attrs(&GSS_MECH_KRB5).expect("Failed to get attrs");
+        assert!(attrs.contains(&GSS_MA_MECH_CONCRETE).unwrap());
+        assert!(attrs.contains(&GSS_MA_AUTH_INIT).unwrap());
+        assert!(!attrs.contains(&GSS_MA_MECH_NEGO).unwrap());
+        assert!(known.contains(&GSS_MA_MECH_NEGO).unwrap());
+        let (attrs, _) = mech_attrs(&GSS_MECH_SPNEGO).expect("Failed to get attrs");
+        assert!(attrs.contains(&GSS_MA_MECH_NEGO).unwrap());
+    }
+
+    #[cfg(feature = "rfc5587")]
+    #[test]
+    fn test_display_mech_attr() {
+        let info = display_mech_attr(&GSS_MA_DELEG_CRED).expect("Failed to display attr");
+        assert_eq!(&*info.name, b"GSS_C_MA_DELEG_CRED");
+        assert!(!info.long_desc.is_empty());
+    }
+
+    #[cfg(feature = "rfc5801")]
+    #[test]
+    fn test_sasl_names() {
+        let info = sasl_name_for_mech(&GSS_MECH_KRB5).expect("Failed to get sasl name");
+        assert_eq!(&*info.sasl_name, b"GS2-KRB5");
+        let mech = mech_for_sasl_name(b"GS2-KRB5").expect("Failed to get mech");
+        assert_eq!(mech, GSS_MECH_KRB5);
+        mech_for_sasl_name(b"GS2-BANANA")
+            .expect_err("Expected error for an unknown sasl name");
+    }
+}
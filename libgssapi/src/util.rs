@@ -1,15 +1,18 @@
 use bytes;
 use libgssapi_sys::{
     gss_buffer_desc, gss_buffer_desc_struct, gss_buffer_t, gss_release_buffer, OM_uint32,
-    GSS_S_COMPLETE,
+    GSS_S_COMPLETE, _GSS_C_INDEFINITE,
 };
-#[cfg(feature = "s4u")]
-use libgssapi_sys::{gss_buffer_set_t, gss_release_buffer_set};
 use std::{
-    ffi,
+    convert::TryFrom,
+    ffi, fmt,
+    io::{self, Write},
     marker::PhantomData,
-    ops::{Deref, DerefMut, Drop},
-    ptr, slice,
+    ops::{Deref, DerefMut, Drop, Index},
+    ptr,
+    slice::{self, SliceIndex},
+    sync::atomic::{compiler_fence, Ordering},
+    time::Duration,
 };
 
 #[cfg(feature = "iov")]
@@ -230,6 +233,24 @@ impl<'a> From<&'a [u8]> for BufRef<'a> {
     }
 }
 
+impl<'a> From<&'a str> for BufRef<'a> {
+    fn from(s: &'a str) -> Self {
+        BufRef::from(s.as_bytes())
+    }
+}
+
+impl<'a> From<&'a String> for BufRef<'a> {
+    fn from(s: &'a String) -> Self {
+        BufRef::from(s.as_bytes())
+    }
+}
+
+impl<'a> From<&'a Vec<u8>> for BufRef<'a> {
+    fn from(v: &'a Vec<u8>) -> Self {
+        BufRef::from(v.as_slice())
+    }
+}
+
 impl<'a> BufRef<'a> {
     pub(crate) unsafe fn to_c(&mut self) -> gss_buffer_t {
         &mut self.0 as gss_buffer_t
@@ -272,6 +293,26 @@ impl DerefMut for Buf {
     }
 }
 
+impl AsRef<[u8]> for Buf {
+    fn as_ref(&self) -> &[u8] {
+        self
+    }
+}
+
+impl AsMut<[u8]> for Buf {
+    fn as_mut(&mut self) -> &mut [u8] {
+        self
+    }
+}
+
+impl<I: SliceIndex<[u8]>> Index<I> for Buf {
+    type Output = I::Output;
+
+    fn index(&self, index: I) -> &Self::Output {
+        &(**self)[index]
+    }
+}
+
 impl Drop for Buf {
     fn drop(&mut self) {
         if !self.0.value.is_null() {
@@ -302,6 +343,126 @@ impl Buf {
     pub fn to_bytes(self) -> GssBytes {
         GssBytes { pos: 0, buf: self }
     }
+
+    /// Copy the contents of the buffer into a `Vec`
+    pub fn to_vec(&self) -> Vec<u8> {
+        (**self).to_vec()
+    }
+
+    /// Copy the contents of the buffer into a `Vec` and release the
+    /// gssapi allocated buffer
+    pub fn into_vec(self) -> Vec<u8> {
+        self.to_vec()
+    }
+
+    /// Write the contents of the buffer directly to `w`, without
+    /// copying it into an intermediate `Vec` first.
+    pub fn write_to<W: Write>(&self, w: &mut W) -> io::Result<()> {
+        w.write_all(self)
+    }
+}
+
+/// Overwrite `buf` with zeros in a way the compiler can't optimize
+/// away, for secrets that are about to be freed
+pub(crate) fn zeroize(buf: &mut [u8]) {
+    for b in buf.iter_mut() {
+        unsafe { ptr::write_volatile(b, 0) }
+    }
+    compiler_fence(Ordering::SeqCst);
+}
+
+/// A `Buf` holding secret material, e.g. a session key. The contents
+/// are overwritten with zeros before the buffer is released, since
+/// gssapi doesn't do that itself. It is returned by
+/// `SecurityContext::inquire_by_oid`, the only wrapper that hands out
+/// key material in a gssapi buffer (the keys of a lucid context are
+/// zeroed by `LucidKey`). Other buffers, e.g. from `Name::export` or
+/// `wrap`, hold names and protected messages rather than keys, so
+/// they stay plain `Buf`s.
+pub struct SecretBuf(Buf);
+
+impl fmt::Debug for SecretBuf {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "SecretBuf(<{} bytes>)", self.0.len())
+    }
+}
+
+impl From<Buf> for SecretBuf {
+    fn from(buf: Buf) -> Self {
+        SecretBuf(buf)
+    }
+}
+
+impl Deref for SecretBuf {
+    type Target = [u8];
+
+    fn deref(&self) -> &Self::Target {
+        &self.0
+    }
+}
+
+impl DerefMut for SecretBuf {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        &mut self.0
+    }
+}
+
+impl Drop for SecretBuf {
+    fn drop(&mut self) {
+        zeroize(&mut self.0)
+    }
+}
+
+/// A credential or context lifetime. Gssapi represents these as a
+/// number of seconds, with the magic value `GSS_C_INDEFINITE` for
+/// "as long as possible" when requesting a lifetime, or "doesn't
+/// expire" when one is returned.
+///
+/// When requesting a lifetime `Seconds(0)` asks for the mechanism's
+/// default, it is not a synonym for `Indefinite`. A returned
+/// `Seconds(0)` means the credential or context has expired.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub enum Lifetime {
+    #[default]
+    Indefinite,
+    Seconds(u32),
+}
+
+/// Durations too long to represent in seconds as a `u32` become
+/// `Indefinite`.
+impl From<Duration> for Lifetime {
+    fn from(d: Duration) -> Self {
+        match u32::try_from(d.as_secs()) {
+            Ok(secs) if secs != _GSS_C_INDEFINITE => Lifetime::Seconds(secs),
+            Ok(_) | Err(_) => Lifetime::Indefinite,
+        }
+    }
+}
+
+impl Lifetime {
+    pub(crate) fn from_c(t: OM_uint32) -> Self {
+        if t == _GSS_C_INDEFINITE {
+            Lifetime::Indefinite
+        } else {
+            Lifetime::Seconds(t)
+        }
+    }
+
+    /// The C representation of this lifetime
+    pub fn to_c(self) -> OM_uint32 {
+        match self {
+            Lifetime::Indefinite => _GSS_C_INDEFINITE,
+            Lifetime::Seconds(secs) => secs,
+        }
+    }
+
+    /// This lifetime as a `Duration`, or `None` if it is indefinite
+    pub fn as_duration(&self) -> Option<Duration> {
+        match self {
+            Lifetime::Indefinite => None,
+            Lifetime::Seconds(secs) => Some(Duration::from_secs(*secs as u64)),
+        }
+    }
 }
 
 #[derive(Debug)]
@@ -339,77 +500,67 @@ impl GssBytes {
     }
 }
 
-#[cfg(feature = "s4u")]
-mod s4u {
+#[cfg(test)]
+mod tests {
     use super::*;
+    use crate::{name::Name, oid::GSS_NT_USER_NAME};
+
+    // a gssapi allocated buffer containing "nobody"
+    fn nobody() -> Buf {
+        Name::new(b"nobody", Some(&GSS_NT_USER_NAME))
+            .expect("Failed to import name")
+            .display_name()
+            .expect("Failed to display name")
+    }
 
-    /// This represents an owned buffer set we got from gssapi, it will be
-    /// deallocated via the library routine when it is dropped.
-    #[repr(transparent)]
-    #[allow(dead_code)]
-    #[derive(Debug)]
-    pub(crate) struct BufSet<'a>(gss_buffer_set_t, PhantomData<&'a [BufRef<'a>]>);
-
-    unsafe impl Send for BufSet<'_> {}
-    unsafe impl Sync for BufSet<'_> {}
-
-    impl<'a> Deref for BufSet<'a> {
-        type Target = [BufRef<'a>];
-
-        fn deref(&self) -> &'a Self::Target {
-            if self.0.is_null()
-                || unsafe { (*self.0).elements.is_null() && (*self.0).count == 0 }
-            {
-                &[]
-            } else {
-                unsafe {
-                    slice::from_raw_parts(
-                        (*self.0).elements.cast(),
-                        (*self.0).count as usize,
-                    )
-                }
-            }
-        }
+    #[test]
+    fn test_write_to() {
+        let buf = nobody();
+        let mut sink = Vec::new();
+        buf.write_to(&mut sink).expect("Failed to write buffer");
+        assert_eq!(&sink[..], &*buf);
+        assert_eq!(&sink[..], b"nobody");
     }
 
-    impl<'a> DerefMut for BufSet<'a> {
-        fn deref_mut(&mut self) -> &'a mut Self::Target {
-            if self.0.is_null()
-                || unsafe { (*self.0).elements.is_null() && (*self.0).count == 0 }
-            {
-                &mut []
-            } else {
-                unsafe {
-                    slice::from_raw_parts_mut(
-                        (*self.0).elements.cast(),
-                        (*self.0).count as usize,
-                    )
-                }
-            }
-        }
+    #[test]
+    fn test_secret_buf() {
+        let buf = SecretBuf::from(nobody());
+        assert_eq!(&*buf, b"nobody");
+        assert_eq!(format!("{:?}", buf), "SecretBuf(<6 bytes>)");
+        let mut key = [1u8, 2, 3];
+        zeroize(&mut key);
+        assert_eq!(key, [0; 3]);
     }
 
-    impl Drop for BufSet<'_> {
-        fn drop(&mut self) {
-            if !self.0.is_null() {
-                let mut minor = GSS_S_COMPLETE;
-                let _major = unsafe {
-                    gss_release_buffer_set(&mut minor as *mut OM_uint32, &mut self.0)
-                };
-            }
-        }
+    #[test]
+    fn test_slice_like() {
+        let buf = nobody();
+        assert_eq!(buf.as_ref(), b"nobody");
+        assert_eq!(&buf[1..3], b"ob");
+        assert_eq!(&buf[..2], b"no");
+        assert_eq!(buf[0], b'n');
+        assert_eq!(buf.to_vec(), b"nobody".to_vec());
+        assert_eq!(buf.into_vec(), b"nobody".to_vec());
     }
 
-    impl BufSet<'_> {
-        pub(crate) fn empty() -> Self {
-            Self(ptr::null_mut(), PhantomData)
-        }
+    #[test]
+    fn test_buf_ref_from() {
+        let s = String::from("token");
+        let v = s.as_bytes().to_vec();
+        assert_eq!(&*BufRef::from("token"), b"token");
+        assert_eq!(&*BufRef::from(&s), b"token");
+        assert_eq!(&*BufRef::from(&v), b"token");
+        assert_eq!(&*BufRef::from(""), b"");
+    }
 
-        pub(crate) unsafe fn to_c(&mut self) -> &mut gss_buffer_set_t {
-            &mut self.0
-        }
+    #[test]
+    fn test_lifetime() {
+        assert_eq!(Lifetime::from(Duration::from_secs(60)), Lifetime::Seconds(60));
+        assert_eq!(Lifetime::from(Duration::from_secs(u64::MAX)), Lifetime::Indefinite);
+        assert_eq!(Lifetime::from_c(_GSS_C_INDEFINITE), Lifetime::Indefinite);
+        assert_eq!(Lifetime::Indefinite.to_c(), _GSS_C_INDEFINITE);
+        assert_eq!(Lifetime::Seconds(0).to_c(), 0);
+        assert_eq!(Lifetime::Seconds(0).as_duration(), Some(Duration::from_secs(0)));
+        assert_eq!(Lifetime::Indefinite.as_duration(), None);
     }
 }
-
-#[cfg(feature = "s4u")]
-pub(crate) use s4u::*;